@@ -0,0 +1,99 @@
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use byteorder::{NetworkEndian, ReadBytesExt};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cqi_rs::*;
+use cqi_rs::cqi_consts::*;
+
+// Minimal stand-in for a CQPserver that answers every CL_CPOS2STR request
+// with one short string per requested corpus position.
+fn spawn_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            thread::spawn(move || serve(stream));
+        }
+    });
+
+    addr
+}
+
+fn serve(stream: TcpStream) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = BufWriter::new(stream);
+
+    while let Ok(command) = reader.read_u16::<NetworkEndian>() {
+        assert_eq!(command, COMMANDS::CL_CPOS2STR as WORD);
+
+        let len = reader.read_u16::<NetworkEndian>().unwrap();
+        let mut attribute = vec![0; len as usize];
+        reader.read_exact(&mut attribute).unwrap();
+
+        let n = reader.read_i32::<NetworkEndian>().unwrap();
+        for _ in 0..n {
+            reader.read_i32::<NetworkEndian>().unwrap();
+        }
+
+        writer.write_all(&(DATA::STRING_LIST as WORD).to_be_bytes()).unwrap();
+        writer.write_all(&n.to_be_bytes()).unwrap();
+        for _ in 0..n {
+            writer.write_all(&[0, 5]).unwrap();
+            writer.write_all(b"token").unwrap();
+        }
+        writer.flush().unwrap();
+    }
+}
+
+// The way commands used to be sent: every value written straight to the
+// socket and every byte read straight from it.
+fn cpos2str_unbuffered(stream: &mut TcpStream, cpos: &[INT]) -> STRING_LIST {
+    (COMMANDS::CL_CPOS2STR as WORD).write_cqi_bytes(stream).unwrap();
+    "BENCH.word".write_cqi_bytes(stream).unwrap();
+    (cpos.len() as INT).write_cqi_bytes(stream).unwrap();
+    for p in cpos {
+        p.write_cqi_bytes(stream).unwrap();
+    }
+
+    assert_eq!(stream.read_u16::<NetworkEndian>().unwrap(), DATA::STRING_LIST as WORD);
+    let n = stream.read_i32::<NetworkEndian>().unwrap();
+
+    (0..n).map(|_| {
+        let len = stream.read_u16::<NetworkEndian>().unwrap();
+        let bytes: Vec<u8> = (0..len).map(|_| stream.read_u8().unwrap()).collect();
+        String::from_utf8(bytes).unwrap()
+    }).collect()
+}
+
+fn bench_cpos2str(c: &mut Criterion) {
+    let addr = spawn_server();
+
+    let mut raw = TcpStream::connect(addr).unwrap();
+    let connection = CQiConnection::new(addr).unwrap();
+    // the stand-in server only knows CL_CPOS2STR
+    connection.set_charset("BENCH", Charset::Utf8);
+
+    let mut group = c.benchmark_group("cl_cpos2str");
+    group.sample_size(10);
+
+    for &n in &[1_000, 100_000] {
+        let cpos: Vec<INT> = (0..n).collect();
+        group.throughput(Throughput::Elements(n as u64));
+
+        group.bench_with_input(BenchmarkId::new("unbuffered", n), &cpos, |b, cpos| {
+            b.iter(|| cpos2str_unbuffered(&mut raw, cpos))
+        });
+
+        group.bench_with_input(BenchmarkId::new("buffered", n), &cpos, |b, cpos| {
+            b.iter(|| connection.cl_cpos2str("BENCH.word", cpos).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_cpos2str);
+criterion_main!(benches);
@@ -0,0 +1,709 @@
+use std::collections::HashMap;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use num_traits::FromPrimitive;
+use crate::*;
+
+macro_rules! send_cqi_data {
+    ( $con:ident, $command:path$(, $( $x:expr ),*)? ) => (
+        {
+            $con.write($command as WORD)?;
+            $(
+                $(
+                    $con.write($x)?;
+                )*
+            )?
+            $con.flush().await
+        }
+    );
+}
+
+macro_rules! receive_cqi_data {
+    ( $con:ident, $data_type:path, $readfun:ident ) => (
+        {
+            $con.expect_response($data_type as WORD).await?;
+            $con.$readfun().await
+        }
+    );
+}
+
+macro_rules! receive_cqi_status {
+    ( $con:ident, $status:path ) => (
+        $con.expect_response($status as WORD).await
+    );
+}
+
+// Non-blocking counterpart of CQiConnection with the same command set.
+// Requests are encoded with CQiData exactly like the blocking client does.
+pub struct AsyncCQiConnection<S: AsyncRead + AsyncWrite + Unpin = TcpStream> {
+    stream: BufReader<S>,
+    // the command currently being assembled, sent as a whole by flush()
+    buffer: Vec<u8>,
+    // fetch CTRL_LAST_GENERAL_ERROR after general errors
+    fetch_error_messages: bool,
+    limits: DecodeLimits,
+    charsets: HashMap<String, Charset>,
+}
+
+impl AsyncCQiConnection<TcpStream> {
+
+    pub async fn connect<A: ToSocketAddrs>(address: A) -> CQiResult<AsyncCQiConnection> {
+        let stream = TcpStream::connect(address).await?;
+        Ok(AsyncCQiConnection::from_stream(stream))
+    }
+}
+
+// Struct methods
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncCQiConnection<S> {
+
+    pub fn from_stream(stream: S) -> AsyncCQiConnection<S> {
+        AsyncCQiConnection {
+            stream: BufReader::new(stream),
+            buffer: Vec::new(),
+            fetch_error_messages: false,
+            limits: DecodeLimits::default(),
+            charsets: HashMap::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        self.stream.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        self.stream.get_mut()
+    }
+
+    // see CQiConnection::set_fetch_error_messages()
+    pub fn set_fetch_error_messages(&mut self, enabled: bool) {
+        self.fetch_error_messages = enabled;
+    }
+
+    // see CQiConnection::charset()
+    pub async fn charset(&mut self, corpus: &str) -> CQiResult<Charset> {
+        let corpus = charset::corpus_of(corpus);
+
+        if let Some(charset) = self.charsets.get(corpus) {
+            return Ok(*charset);
+        }
+
+        let name = self.corpus_charset(corpus).await?;
+        let charset = match Charset::from_name(&name) {
+            Some(charset) => charset,
+            None => return Err(CQiError::InvalidData(format!("unknown charset \"{}\" of corpus {}", name, corpus))),
+        };
+
+        self.charsets.insert(corpus.to_owned(), charset);
+        Ok(charset)
+    }
+
+    // see CQiConnection::set_charset()
+    pub fn set_charset(&mut self, corpus: &str, charset: Charset) {
+        self.charsets.insert(charset::corpus_of(corpus).to_owned(), charset);
+    }
+
+    async fn encode_strings(&mut self, corpus: &str, strings: &[&str]) -> CQiResult<Vec<RawString>> {
+        let charset = self.charset(corpus).await?;
+        strings.iter().map(|s| Ok(RawString(charset.encode(s)?.into_owned()))).collect()
+    }
+
+    async fn decode_strings(&mut self, corpus: &str, strings: Vec<RawString>) -> CQiResult<STRING_LIST> {
+        let charset = self.charset(corpus).await?;
+        strings.into_iter().map(|s| charset.decode(s.0)).collect()
+    }
+
+    pub fn set_decode_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
+    }
+
+    // discards the pending command if <data> can't be encoded
+    pub fn write<A: CQiData>(&mut self, data: A) -> CQiResult<()> {
+        let result = data.write_cqi_bytes(&mut self.buffer);
+        if result.is_err() {
+            self.buffer.clear();
+        }
+        result
+    }
+
+    pub async fn flush(&mut self) -> CQiResult<()> {
+        if !self.buffer.is_empty() {
+            let stream = self.stream.get_mut();
+            stream.write_all(&self.buffer).await?;
+            stream.flush().await?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    async fn reader(&mut self) -> CQiResult<&mut BufReader<S>> {
+        self.flush().await?;
+        Ok(&mut self.stream)
+    }
+
+    // Reads the complete encoding of a <datatype> value, so that it can be
+    // decoded with the blocking CQiDecode implementations. Length prefixes
+    // are checked here already, before anything is buffered for them.
+    async fn read_frame(&mut self, datatype: DATA) -> CQiResult<Vec<u8>> {
+        let limits = self.limits;
+        let mut frame = Vec::new();
+
+        match datatype {
+            DATA::BYTE | DATA::BOOL => self.read_bytes(&mut frame, 1).await?,
+            DATA::INT => self.read_bytes(&mut frame, 4).await?,
+            DATA::INT_INT => self.read_bytes(&mut frame, 8).await?,
+            DATA::INT_INT_INT_INT => self.read_bytes(&mut frame, 16).await?,
+            DATA::STRING => self.read_string_frame(&mut frame).await?,
+            DATA::BYTE_LIST | DATA::BOOL_LIST => {
+                let len = self.read_int_frame(&mut frame).await?;
+                let len = decode::check_len(len, limits.max_list_len, "list")?;
+                self.read_bytes(&mut frame, len).await?;
+            },
+            DATA::INT_LIST => {
+                let len = self.read_int_frame(&mut frame).await?;
+                let len = decode::check_len(len, limits.max_list_len, "list")?;
+                self.read_bytes(&mut frame, 4 * len).await?;
+            },
+            DATA::STRING_LIST => {
+                let len = self.read_int_frame(&mut frame).await?;
+                let len = decode::check_len(len, limits.max_list_len, "list")?;
+                for _ in 0..len {
+                    self.read_string_frame(&mut frame).await?;
+                }
+            },
+            DATA::INT_TABLE => {
+                let rows = self.read_int_frame(&mut frame).await?;
+                let cols = self.read_int_frame(&mut frame).await?;
+                let (rows, cols) = decode::check_table(rows, cols, &limits)?;
+                self.read_bytes(&mut frame, 4 * rows * cols).await?;
+            },
+        }
+
+        Ok(frame)
+    }
+
+    // grows the frame chunk by chunk, so a bogus length fails on EOF before
+    // it can cause a huge allocation
+    async fn read_bytes(&mut self, frame: &mut Vec<u8>, mut len: usize) -> CQiResult<()> {
+        while len > 0 {
+            let chunk = len.min(1 << 16);
+            let start = frame.len();
+            frame.resize(start + chunk, 0);
+            self.reader().await?.read_exact(&mut frame[start..]).await?;
+            len -= chunk;
+        }
+        Ok(())
+    }
+
+    async fn read_int_frame(&mut self, frame: &mut Vec<u8>) -> CQiResult<INT> {
+        let value = self.read_int().await?;
+        frame.extend_from_slice(&value.to_be_bytes());
+        Ok(value)
+    }
+
+    async fn read_string_frame(&mut self, frame: &mut Vec<u8>) -> CQiResult<()> {
+        let len = self.read_word().await?;
+        if len as usize > self.limits.max_string_len {
+            return Err(CQiError::LimitExceeded { what: "string", len: len as usize, limit: self.limits.max_string_len });
+        }
+        frame.extend_from_slice(&len.to_be_bytes());
+        self.read_bytes(frame, len as usize).await
+    }
+
+    pub async fn read<T: CQiDecode>(&mut self, datatype: DATA) -> CQiResult<T> {
+        let frame = self.read_frame(datatype).await?;
+        T::read_cqi_bytes(&mut &frame[..], &self.limits)
+    }
+
+    // reads a value whose type is only known from its DATA header
+    pub async fn read_value(&mut self, datatype: DATA) -> CQiResult<CQiValue> {
+        let frame = self.read_frame(datatype).await?;
+        CQiValue::read_cqi_value(datatype, &mut &frame[..], &self.limits)
+    }
+
+    pub async fn read_word(&mut self) -> CQiResult<WORD> {
+        Ok(self.reader().await?.read_u16().await?)
+    }
+
+    pub async fn read_int(&mut self) -> CQiResult<INT> {
+        Ok(self.reader().await?.read_i32().await?)
+    }
+
+    pub async fn read_bool(&mut self) -> CQiResult<BOOL> {
+        self.read(DATA::BOOL).await
+    }
+
+    pub async fn read_byte(&mut self) -> CQiResult<BYTE> {
+        self.read(DATA::BYTE).await
+    }
+
+    pub async fn read_string(&mut self) -> CQiResult<STRING> {
+        self.read(DATA::STRING).await
+    }
+
+    pub async fn read_bool_list(&mut self) -> CQiResult<BOOL_LIST> {
+        self.read(DATA::BOOL_LIST).await
+    }
+
+    pub async fn read_byte_list(&mut self) -> CQiResult<BYTE_LIST> {
+        self.read(DATA::BYTE_LIST).await
+    }
+
+    pub async fn read_int_list(&mut self) -> CQiResult<INT_LIST> {
+        self.read(DATA::INT_LIST).await
+    }
+
+    pub async fn read_string_list(&mut self) -> CQiResult<STRING_LIST> {
+        self.read(DATA::STRING_LIST).await
+    }
+
+    pub async fn read_raw_string(&mut self) -> CQiResult<RawString> {
+        self.read(DATA::STRING).await
+    }
+
+    pub async fn read_raw_string_list(&mut self) -> CQiResult<Vec<RawString>> {
+        self.read(DATA::STRING_LIST).await
+    }
+
+    pub async fn read_int_int(&mut self) -> CQiResult<INT_INT> {
+        self.read(DATA::INT_INT).await
+    }
+
+    pub async fn read_int_int_int_int(&mut self) -> CQiResult<INT_INT_INT_INT> {
+        self.read(DATA::INT_INT_INT_INT).await
+    }
+
+    pub async fn read_int_table(&mut self) -> CQiResult<INT_TABLE> {
+        self.read(DATA::INT_TABLE).await
+    }
+
+    async fn read_fdist_table(&mut self, cols: usize) -> CQiResult<INT_TABLE> {
+        let table = self.read_int_table().await?;
+
+        if table.iter().any(|row| row.len() != cols) {
+            return Err(CQiError::InvalidData(format!("expected frequency table with {} columns", cols)));
+        }
+
+        Ok(table)
+    }
+
+    async fn expect_response(&mut self, expected: WORD) -> CQiResult<()> {
+        let r = self.read_word().await?;
+
+        if r == expected {
+            return Ok(());
+        }
+
+        let error = CQiError::from_response(expected, r);
+
+        // see CQiConnection::expect_response()
+        if let Some(datatype) = DATA::from_u16(r) {
+            let _ = self.read_frame(datatype).await;
+        }
+
+        if self.fetch_error_messages && error.is_general() {
+            // a failure here must not hide the original error, this is
+            // ctrl_last_general_error() without the recursion
+            self.write(COMMANDS::CTRL_LAST_GENERAL_ERROR as WORD)?;
+            if let Ok(r) = self.read_word().await {
+                if r == DATA::STRING as WORD {
+                    if let Ok(message) = self.read_string().await {
+                        return Err(error.with_message(message));
+                    }
+                }
+            }
+        }
+
+        Err(error)
+    }
+}
+
+// CQi commands
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncCQiConnection<S> {
+
+    pub async fn ctr_connect(&mut self, user: &str, password: &str) -> CQiResult<()> {
+        send_cqi_data!(self,
+            COMMANDS::CTRL_CONNECT,
+            user,
+            password
+        )?;
+        receive_cqi_status!(self, STATUS::CONNECT_OK)
+    }
+
+    pub async fn ctrl_ping(&mut self) -> CQiResult<()> {
+        send_cqi_data!(self,
+            COMMANDS::CTRL_PING
+        )?;
+        receive_cqi_status!(self, STATUS::PING_OK)
+    }
+
+    pub async fn ctrl_last_general_error(&mut self) -> CQiResult<STRING> {
+        send_cqi_data!(self,
+            COMMANDS::CTRL_LAST_GENERAL_ERROR
+        )?;
+        receive_cqi_data!(self, DATA::STRING, read_string)
+    }
+
+    pub async fn corpus_list_corpora(&mut self) -> CQiResult<STRING_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_LIST_CORPORA
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_string_list)
+    }
+
+    pub async fn corpus_charset(&mut self, corpus: &str) -> CQiResult<STRING> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_CHARSET,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING, read_string)
+    }
+
+    pub async fn corpus_properties(&mut self, corpus: &str) -> CQiResult<STRING_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_PROPERTIES,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_string_list)
+    }
+
+    pub async fn corpus_positional_attributes(&mut self, corpus: &str) -> CQiResult<STRING_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_POSITIONAL_ATTRIBUTES,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_string_list)
+    }
+
+    pub async fn corpus_structural_attributes(&mut self, corpus: &str) -> CQiResult<STRING_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_STRUCTURAL_ATTRIBUTES,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_string_list)
+    }
+
+    pub async fn corpus_structural_attribute_has_values(&mut self, attribute: &str) -> CQiResult<BOOL> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_STRUCTURAL_ATTRIBUTE_HAS_VALUES,
+            attribute
+        )?;
+        receive_cqi_data!(self, DATA::BOOL, read_bool)
+    }
+
+    pub async fn corpus_alignment_attributes(&mut self, corpus: &str) -> CQiResult<STRING_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_ALIGNMENT_ATTRIBUTES,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_string_list)
+    }
+
+    // decoded with the corpus charset like the CL_* strings
+    pub async fn corpus_full_name(&mut self, corpus: &str) -> CQiResult<STRING> {
+        let name = self.corpus_full_name_raw(corpus).await?;
+        self.charset(corpus).await?.decode(name.0)
+    }
+
+    pub async fn corpus_full_name_raw(&mut self, corpus: &str) -> CQiResult<RawString> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_FULL_NAME,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING, read_raw_string)
+    }
+
+    pub async fn corpus_info(&mut self, corpus: &str) -> CQiResult<STRING_LIST> {
+        let info = self.corpus_info_raw(corpus).await?;
+        self.decode_strings(corpus, info).await
+    }
+
+    pub async fn corpus_info_raw(&mut self, corpus: &str) -> CQiResult<Vec<RawString>> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_INFO,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_raw_string_list)
+    }
+
+    pub async fn corpus_drop_corpus(&mut self, corpus: &str) -> CQiResult<()> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_DROP_CORPUS,
+            corpus
+        )?;
+        receive_cqi_status!(self, STATUS::OK)
+    }
+
+    pub async fn cl_attribute_size(&mut self, attribute: &str) -> CQiResult<INT> {
+        send_cqi_data!(self,
+            COMMANDS::CL_ATTRIBUTE_SIZE,
+            attribute
+        )?;
+        receive_cqi_data!(self, DATA::INT, read_int)
+    }
+
+    pub async fn cl_lexicon_size(&mut self, attribute: &str) -> CQiResult<INT> {
+        send_cqi_data!(self,
+            COMMANDS::CL_LEXICON_SIZE,
+            attribute
+        )?;
+        receive_cqi_data!(self, DATA::INT, read_int)
+    }
+
+    pub async fn cl_drop_attribute(&mut self, attribute: &str) -> CQiResult<()> {
+        send_cqi_data!(self,
+            COMMANDS::CL_DROP_ATTRIBUTE,
+            attribute
+        )?;
+        receive_cqi_status!(self, STATUS::OK)
+    }
+
+    pub async fn cl_str2id(&mut self, attribute: &str, strings: &[&str]) -> CQiResult<INT_LIST> {
+        let strings = self.encode_strings(attribute, strings).await?;
+        self.cl_str2id_raw(attribute, &strings).await
+    }
+
+    pub async fn cl_str2id_raw(&mut self, attribute: &str, strings: &[RawString]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_STR2ID,
+            attribute,
+            strings
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub async fn cl_id2str(&mut self, attribute: &str, ids: &[INT]) -> CQiResult<STRING_LIST> {
+        let strings = self.cl_id2str_raw(attribute, ids).await?;
+        self.decode_strings(attribute, strings).await
+    }
+
+    pub async fn cl_id2str_raw(&mut self, attribute: &str, ids: &[INT]) -> CQiResult<Vec<RawString>> {
+        send_cqi_data!(self,
+            COMMANDS::CL_ID2STR,
+            attribute,
+            ids
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_raw_string_list)
+    }
+
+    pub async fn cl_id2freq(&mut self, attribute: &str, ids: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_ID2FREQ,
+            attribute,
+            ids
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub async fn cl_cpos2id(&mut self, attribute: &str, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_CPOS2ID,
+            attribute,
+            cpos
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub async fn cl_cpos2str(&mut self, attribute: &str, cpos: &[INT]) -> CQiResult<STRING_LIST> {
+        let strings = self.cl_cpos2str_raw(attribute, cpos).await?;
+        self.decode_strings(attribute, strings).await
+    }
+
+    pub async fn cl_cpos2str_raw(&mut self, attribute: &str, cpos: &[INT]) -> CQiResult<Vec<RawString>> {
+        send_cqi_data!(self,
+            COMMANDS::CL_CPOS2STR,
+            attribute,
+            cpos
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_raw_string_list)
+    }
+
+    pub async fn cl_cpos2struc(&mut self, attribute: &str, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_CPOS2STRUC,
+            attribute,
+            cpos
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub async fn cl_cpos2lbound(&mut self, attribute: &str, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_CPOS2LBOUND,
+            attribute,
+            cpos
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub async fn cl_cpos2rbound(&mut self, attribute: &str, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_CPOS2RBOUND,
+            attribute,
+            cpos
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub async fn cl_cpos2alg(&mut self, attribute: &str, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_CPOS2ALG,
+            attribute,
+            cpos
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub async fn cl_struc2str(&mut self, attribute: &str, strucs: &[INT]) -> CQiResult<STRING_LIST> {
+        let strings = self.cl_struc2str_raw(attribute, strucs).await?;
+        self.decode_strings(attribute, strings).await
+    }
+
+    pub async fn cl_struc2str_raw(&mut self, attribute: &str, strucs: &[INT]) -> CQiResult<Vec<RawString>> {
+        send_cqi_data!(self,
+            COMMANDS::CL_STRUC2STR,
+            attribute,
+            strucs
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_raw_string_list)
+    }
+
+    pub async fn cl_id2cpos(&mut self, attribute: &str, id: INT) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_ID2CPOS,
+            attribute,
+            id
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub async fn cl_idlist2cpos(&mut self, attribute: &str, id_list: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_IDLIST2CPOS,
+            attribute,
+            id_list
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub async fn cl_regex2id(&mut self, attribute: &str, regex: &str) -> CQiResult<INT_LIST> {
+        let regex = self.charset(attribute).await?.encode(regex)?.into_owned();
+        send_cqi_data!(self,
+            COMMANDS::CL_REGEX2ID,
+            attribute,
+            RawString(regex)
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub async fn cl_struc2cpos(&mut self, attribute: &str, struc: INT) -> CQiResult<INT_INT> {
+        send_cqi_data!(self,
+            COMMANDS::CL_STRUC2CPOS,
+            attribute,
+            struc
+        )?;
+        receive_cqi_data!(self, DATA::INT_INT, read_int_int)
+    }
+
+    pub async fn cl_alg2cpos(&mut self, attribute: &str, alg: INT) -> CQiResult<INT_INT_INT_INT> {
+        send_cqi_data!(self,
+            COMMANDS::CL_ALG2CPOS,
+            attribute,
+            alg
+        )?;
+        receive_cqi_data!(self, DATA::INT_INT_INT_INT, read_int_int_int_int)
+    }
+
+    // see CQiConnection::cqp_query()
+    pub async fn cqp_query(&mut self, mother_corpus: &str, subcorpus_name: &str, query: &str) -> CQiResult<()> {
+        let query = subcorpus::terminate_query(query);
+        let query = self.charset(mother_corpus).await?.encode(&query)?.into_owned();
+        self.cqp_query_raw(mother_corpus, subcorpus_name, &query).await
+    }
+
+    pub async fn cqp_query_raw(&mut self, mother_corpus: &str, subcorpus_name: &str, query: &[u8]) -> CQiResult<()> {
+        subcorpus::check_name(subcorpus_name)?;
+        send_cqi_data!(self,
+            COMMANDS::CQP_QUERY,
+            mother_corpus,
+            subcorpus_name,
+            RawString::from(query)
+        )?;
+        receive_cqi_status!(self, STATUS::OK)
+    }
+
+    pub async fn cqp_list_subcorpora(&mut self, corpus: &str) -> CQiResult<STRING_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_LIST_SUBCORPORA,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_string_list)
+    }
+
+    pub async fn cqp_subcorpus_size(&mut self, subcorpus: &str) -> CQiResult<INT> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_SUBCORPUS_SIZE,
+            subcorpus
+        )?;
+        receive_cqi_data!(self, DATA::INT, read_int)
+    }
+
+    pub async fn cqp_subcorpus_has_field(&mut self, subcorpus: &str, field: FIELD) -> CQiResult<BOOL> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_SUBCORPUS_HAS_FIELD,
+            subcorpus,
+            field as BYTE
+        )?;
+        receive_cqi_data!(self, DATA::BOOL, read_bool)
+    }
+
+    pub async fn cqp_dump_subcorpus(&mut self, subcorpus: &str, field: FIELD, first: INT, last: INT) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_DUMP_SUBCORPUS,
+            subcorpus,
+            field as BYTE,
+            first,
+            last
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub async fn cqp_drop_subcorpus(&mut self, subcorpus: &str) -> CQiResult<()> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_DROP_SUBCORPUS,
+            subcorpus
+        )?;
+        receive_cqi_status!(self, STATUS::OK)
+    }
+
+    pub async fn cqp_fdist_1(&mut self, subcorpus: &str, cutoff: INT, field: FIELD, attribute: &str) -> CQiResult<Vec<FdistItem>> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_FDIST_1,
+            subcorpus,
+            cutoff,
+            field as BYTE,
+            attribute
+        )?;
+        self.expect_response(DATA::INT_TABLE as WORD).await?;
+
+        let table = self.read_fdist_table(2).await?;
+
+        Ok(table.iter().map(|row| FdistItem { id: row[0], frequency: row[1] }).collect())
+    }
+
+    pub async fn cqp_fdist_2(&mut self, subcorpus: &str, cutoff: INT, field1: FIELD, attribute1: &str, field2: FIELD, attribute2: &str) -> CQiResult<Vec<FdistPair>> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_FDIST_2,
+            subcorpus,
+            cutoff,
+            field1 as BYTE,
+            attribute1,
+            field2 as BYTE,
+            attribute2
+        )?;
+        self.expect_response(DATA::INT_TABLE as WORD).await?;
+
+        let table = self.read_fdist_table(3).await?;
+
+        Ok(table.iter().map(|row| FdistPair { id1: row[0], id2: row[1], frequency: row[2] }).collect())
+    }
+}
@@ -0,0 +1,204 @@
+use std::cell::{Cell, OnceCell};
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use crate::corpus::cached;
+use crate::*;
+
+// Handles on the attributes of a corpus, see Corpus::p_attribute() etc.
+// They only carry the "CORPUS.attribute" specifier and share the connection.
+// Only the CL_* commands valid for the kind of attribute are offered.
+macro_rules! attribute_handle {
+    ( $name:ident$(, $field:ident: $type:ty)* ) => (
+        pub struct $name<'a, S: Read + Write = TcpStream> {
+            connection: &'a CQiConnection<S>,
+            specifier: String,
+            $(
+                $field: $type,
+            )*
+        }
+
+        impl<'a, S: Read + Write> $name<'a, S> {
+
+            pub(crate) fn new(connection: &'a CQiConnection<S>, specifier: String) -> $name<'a, S> {
+                $name { connection, specifier$(, $field: Default::default())* }
+            }
+
+            // the attribute name without the corpus
+            pub fn name(&self) -> &str {
+                &self.specifier[charset::corpus_of(&self.specifier).len() + 1..]
+            }
+
+            pub fn corpus_name(&self) -> &str {
+                charset::corpus_of(&self.specifier)
+            }
+
+            pub fn specifier(&self) -> &str {
+                &self.specifier
+            }
+
+            pub fn connection(&self) -> &'a CQiConnection<S> {
+                self.connection
+            }
+
+            // tokens, regions or alignment beads
+            pub fn size(&self) -> CQiResult<INT> {
+                self.connection.cl_attribute_size(&self.specifier)
+            }
+
+            // frees the server's cached data for this attribute
+            pub fn drop_attribute(&self) -> CQiResult<()> {
+                self.connection.cl_drop_attribute(&self.specifier)
+            }
+        }
+
+        impl<S: Read + Write> Clone for $name<'_, S> {
+            fn clone(&self) -> Self {
+                $name { connection: self.connection, specifier: self.specifier.clone()$(, $field: self.$field.clone())* }
+            }
+        }
+
+        impl<S: Read + Write> fmt::Debug for $name<'_, S> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), self.specifier)
+            }
+        }
+    );
+}
+
+attribute_handle!(PositionalAttribute);
+attribute_handle!(StructuralAttribute, has_values: Cell<Option<BOOL>>, regions: OnceCell<Vec<INT_INT>>);
+attribute_handle!(AlignmentAttribute);
+
+impl<S: Read + Write> PositionalAttribute<'_, S> {
+
+    pub fn lexicon_size(&self) -> CQiResult<INT> {
+        self.connection.cl_lexicon_size(&self.specifier)
+    }
+
+    pub fn str2id(&self, strings: &[&str]) -> CQiResult<INT_LIST> {
+        self.connection.cl_str2id(&self.specifier, strings)
+    }
+
+    pub fn str2id_raw(&self, strings: &[RawString]) -> CQiResult<INT_LIST> {
+        self.connection.cl_str2id_raw(&self.specifier, strings)
+    }
+
+    pub fn id2str(&self, ids: &[INT]) -> CQiResult<STRING_LIST> {
+        self.connection.cl_id2str(&self.specifier, ids)
+    }
+
+    pub fn id2str_raw(&self, ids: &[INT]) -> CQiResult<Vec<RawString>> {
+        self.connection.cl_id2str_raw(&self.specifier, ids)
+    }
+
+    pub fn id2freq(&self, ids: &[INT]) -> CQiResult<INT_LIST> {
+        self.connection.cl_id2freq(&self.specifier, ids)
+    }
+
+    pub fn cpos2id(&self, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        self.connection.cl_cpos2id(&self.specifier, cpos)
+    }
+
+    pub fn cpos2str(&self, cpos: &[INT]) -> CQiResult<STRING_LIST> {
+        self.connection.cl_cpos2str(&self.specifier, cpos)
+    }
+
+    pub fn cpos2str_raw(&self, cpos: &[INT]) -> CQiResult<Vec<RawString>> {
+        self.connection.cl_cpos2str_raw(&self.specifier, cpos)
+    }
+
+    pub fn id2cpos(&self, id: INT) -> CQiResult<INT_LIST> {
+        self.connection.cl_id2cpos(&self.specifier, id)
+    }
+
+    pub fn idlist2cpos(&self, ids: &[INT]) -> CQiResult<INT_LIST> {
+        self.connection.cl_idlist2cpos(&self.specifier, ids)
+    }
+
+    pub fn regex2id(&self, regex: &str) -> CQiResult<INT_LIST> {
+        self.connection.cl_regex2id(&self.specifier, regex)
+    }
+
+    // How each of <ids> is spread over the regions of <parts>, see Dispersion.
+    // One CL_ID2CPOS and one CL_CPOS2STRUC per ID, plus parts.regions() the
+    // first time the handle is used: pass the same <parts> handle for every
+    // call to not fetch the regions again.
+    pub fn dispersion(&self, ids: &[INT], parts: &StructuralAttribute<S>) -> CQiResult<Vec<Dispersion>> {
+        dispersion::dispersion(self, ids, parts)
+    }
+
+    // All of <ids> taken as one item, e.g. the IDs from regex2id(). One
+    // CL_IDLIST2CPOS and one CL_CPOS2STRUC, plus the regions as for
+    // dispersion().
+    pub fn group_dispersion(&self, ids: &[INT], parts: &StructuralAttribute<S>) -> CQiResult<Dispersion> {
+        dispersion::group_dispersion(self, ids, parts)
+    }
+}
+
+impl<S: Read + Write> StructuralAttribute<'_, S> {
+
+    // asked once per handle
+    pub fn has_values(&self) -> CQiResult<BOOL> {
+        if let Some(has_values) = self.has_values.get() {
+            return Ok(has_values);
+        }
+
+        let has_values = self.connection.corpus_structural_attribute_has_values(&self.specifier)?;
+        self.has_values.set(Some(has_values));
+        Ok(has_values)
+    }
+
+    pub fn cpos2struc(&self, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        self.connection.cl_cpos2struc(&self.specifier, cpos)
+    }
+
+    pub fn cpos2lbound(&self, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        self.connection.cl_cpos2lbound(&self.specifier, cpos)
+    }
+
+    pub fn cpos2rbound(&self, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        self.connection.cl_cpos2rbound(&self.specifier, cpos)
+    }
+
+    pub fn struc2cpos(&self, struc: INT) -> CQiResult<INT_INT> {
+        self.connection.cl_struc2cpos(&self.specifier, struc)
+    }
+
+    // First and last position of every region, asked once per handle with
+    // one CL_STRUC2CPOS per region as there is no list variant of the
+    // command.
+    pub fn regions(&self) -> CQiResult<&[INT_INT]> {
+        cached(&self.regions, || (0..self.size()?).map(|struc| self.struc2cpos(struc)).collect()).map(Vec::as_slice)
+    }
+
+    pub fn struc2str(&self, strucs: &[INT]) -> CQiResult<STRING_LIST> {
+        self.check_values()?;
+        self.connection.cl_struc2str(&self.specifier, strucs)
+    }
+
+    pub fn struc2str_raw(&self, strucs: &[INT]) -> CQiResult<Vec<RawString>> {
+        self.check_values()?;
+        self.connection.cl_struc2str_raw(&self.specifier, strucs)
+    }
+
+    fn check_values(&self) -> CQiResult<()> {
+        if self.has_values()? {
+            Ok(())
+        } else {
+            Err(CQiError::InvalidArgument(format!("structural attribute {} has no values", self.specifier)))
+        }
+    }
+}
+
+impl<S: Read + Write> AlignmentAttribute<'_, S> {
+
+    pub fn cpos2alg(&self, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        self.connection.cl_cpos2alg(&self.specifier, cpos)
+    }
+
+    // source start, source end, target start, target end
+    pub fn alg2cpos(&self, alg: INT) -> CQiResult<INT_INT_INT_INT> {
+        self.connection.cl_alg2cpos(&self.specifier, alg)
+    }
+}
@@ -1,13 +1,10 @@
 use cqi_rs::*;
-use std::io::Result as IoResult;
 use cqi_rs::cqi_consts::*;
 use num_traits::FromPrimitive;
-use std::io::{Read, Write};
-use std::fs::File;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-fn main() -> IoResult<()> {
+fn main() -> CQiResult<()> {
     let mut connection = CQiConnection::new("localhost:4877")?;
 
     // automatically login in the beginning
@@ -57,7 +54,7 @@ fn main() -> IoResult<()> {
     Ok(())
 }
 
-fn process_line(connection: &mut CQiConnection, line: &String) -> IoResult<()>{    
+fn process_line(connection: &mut CQiConnection, line: &str) -> CQiResult<()>{    
     let mut cqi_data = vec!();
 
     for token in line.split_ascii_whitespace() {
@@ -70,45 +67,26 @@ fn process_line(connection: &mut CQiConnection, line: &String) -> IoResult<()>{
         }
     }
 
-    if cqi_data.len() > 0 {
+    if !cqi_data.is_empty() {
         println!("Sending {} CQi data object(s): {:?}", cqi_data.len(), cqi_data);
         
         for data in cqi_data {
             connection.write_boxed(data)?;
         }
 
-        let mut buf: Vec<u8> = vec![0; 2];
-        connection.stream.read_exact(&mut buf)?;
+        connection.flush()?;
+        let r = connection.read_word()?;
         print!("Response: ");
-        let mut res_bytes = [0; 2];
-        res_bytes.copy_from_slice(&buf[..2]);
         
-        let datatype = parse_response(u16::from_be_bytes(res_bytes));
+        let datatype = parse_response(r);
 
         match datatype {
             Some(datatype) => {
-                let data: Box<dyn CQiData> = match datatype {
-                    DATA::BYTE => Box::new(connection.read_byte()?),
-                    DATA::BOOL => Box::new(connection.read_bool()?),
-                    DATA::INT => Box::new(connection.read_int()?),
-                    DATA::STRING => Box::new(connection.read_string()?),
-                    DATA::BYTE_LIST => Box::new(connection.read_byte_list()?),
-                    DATA::BOOL_LIST => Box::new(connection.read_bool_list()?),
-                    DATA::INT_LIST => Box::new(connection.read_int_list()?),
-                    DATA::STRING_LIST => Box::new(connection.read_string_list()?),
-                    DATA::INT_INT => Box::new(connection.read_int_int()?),
-                    DATA::INT_INT_INT_INT => Box::new(connection.read_int_int_int_int()?),
-                    DATA::INT_TABLE => Box::new(connection.read_int_table()?),
-                };
-                println!(" {:?}", data);
+                let data = connection.read_value(datatype)?;
+                println!(" {}", data.repr());
             },
             None => println!(),
         }
-
-        // kinda not working right now
-        // let mut f = File::create("response.bin")?;
-        // f.write_all(&buf)?;
-        
     }
 
     Ok(())
@@ -170,7 +148,7 @@ fn parse_command(token: &str) -> Option<Box<WORD>> {
 fn parse_num_type(token: &str) -> Option<Box<dyn CQiData>> {
     let frags: Vec<&str> = token.split(":").collect();
 
-    if frags.len() >= 1 {
+    if !frags.is_empty() {
         let mut num = frags[0];
         let ntype = if frags.len() >= 2 { frags[1] } else { "byte" };
         let mut radix = 10;
@@ -0,0 +1,180 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use socket2::{SockRef, TcpKeepalive};
+use crate::cqi_consts::*;
+use crate::{CQiConnection, CQiResult, DecodeLimits, WORD};
+
+// Options for opening a TCP connection to a CQi server.
+//
+//     let connection = CQiConnectionBuilder::new()
+//         .read_timeout(Some(Duration::from_secs(10)))
+//         .command_timeout(COMMANDS::CQP_QUERY, None)
+//         .credentials("user", "password")
+//         .connect(("localhost", PORT))?;
+#[derive(Clone)]
+pub struct CQiConnectionBuilder {
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    command_timeouts: HashMap<WORD, Option<Duration>>,
+    nodelay: bool,
+    keepalive: Option<Duration>,
+    credentials: Option<(String, String)>,
+    auto_login: bool,
+    limits: DecodeLimits,
+}
+
+impl Default for CQiConnectionBuilder {
+    fn default() -> CQiConnectionBuilder {
+        let dur = Duration::from_secs(2);
+
+        CQiConnectionBuilder {
+            connect_timeout: None,
+            read_timeout: Some(dur),
+            write_timeout: Some(dur),
+            command_timeouts: HashMap::new(),
+            nodelay: false,
+            keepalive: None,
+            credentials: None,
+            auto_login: true,
+            limits: DecodeLimits::default(),
+        }
+    }
+}
+
+impl CQiConnectionBuilder {
+
+    pub fn new() -> CQiConnectionBuilder {
+        CQiConnectionBuilder::default()
+    }
+
+    // None blocks until the operating system gives up
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> CQiConnectionBuilder {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    // None waits forever for responses
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> CQiConnectionBuilder {
+        self.read_timeout = timeout;
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> CQiConnectionBuilder {
+        self.write_timeout = timeout;
+        self
+    }
+
+    // Replaces the read timeout while waiting for the response to <command>,
+    // e.g. for CQP_QUERY or CQP_FDIST_2 on large corpora.
+    pub fn command_timeout(mut self, command: COMMANDS, timeout: Option<Duration>) -> CQiConnectionBuilder {
+        self.command_timeouts.insert(command as WORD, timeout);
+        self
+    }
+
+    pub fn nodelay(mut self, nodelay: bool) -> CQiConnectionBuilder {
+        self.nodelay = nodelay;
+        self
+    }
+
+    // idle time before TCP keepalive probes are sent, None disables keepalive
+    pub fn keepalive(mut self, time: Option<Duration>) -> CQiConnectionBuilder {
+        self.keepalive = time;
+        self
+    }
+
+    pub fn credentials(mut self, user: &str, password: &str) -> CQiConnectionBuilder {
+        self.credentials = Some((user.to_owned(), password.to_owned()));
+        self
+    }
+
+    // Log in with the configured credentials right after connecting (default).
+    // Otherwise CQiConnection::login() has to be called before any command.
+    pub fn auto_login(mut self, auto_login: bool) -> CQiConnectionBuilder {
+        self.auto_login = auto_login;
+        self
+    }
+
+    pub fn decode_limits(mut self, limits: DecodeLimits) -> CQiConnectionBuilder {
+        self.limits = limits;
+        self
+    }
+
+    pub fn connect<A: ToSocketAddrs>(self, address: A) -> CQiResult<CQiConnection> {
+        let stream = match self.connect_timeout {
+            Some(timeout) => connect_timeout(address, timeout)?,
+            None => TcpStream::connect(address)?,
+        };
+
+        stream.set_read_timeout(self.read_timeout)?;
+        stream.set_write_timeout(self.write_timeout)?;
+        stream.set_nodelay(self.nodelay)?;
+
+        if let Some(time) = self.keepalive {
+            SockRef::from(&stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+
+        let timeouts = if self.command_timeouts.is_empty() {
+            None
+        } else {
+            Some(Timeouts {
+                socket: stream.try_clone()?,
+                read: self.read_timeout,
+                current: self.read_timeout,
+                commands: self.command_timeouts,
+            })
+        };
+
+        let mut connection = CQiConnection::from_stream(stream);
+        connection.timeouts = RefCell::new(timeouts);
+        connection.credentials = self.credentials;
+        connection.limits = self.limits;
+
+        if self.auto_login && connection.credentials.is_some() {
+            connection.login()?;
+        }
+
+        Ok(connection)
+    }
+}
+
+fn connect_timeout<A: ToSocketAddrs>(address: A, timeout: Duration) -> CQiResult<TcpStream> {
+    let mut last_error = IoError::new(IoErrorKind::InvalidInput, "could not resolve to any address");
+
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error.into())
+}
+
+// Per-command read timeouts, applied to a handle on the connection's socket.
+#[derive(Debug)]
+pub(crate) struct Timeouts {
+    socket: TcpStream,
+    read: Option<Duration>,
+    current: Option<Duration>,
+    commands: HashMap<WORD, Option<Duration>>,
+}
+
+impl Timeouts {
+    pub(crate) fn apply(&mut self, command: WORD) -> CQiResult<()> {
+        let timeout = match self.commands.get(&command) {
+            Some(timeout) => *timeout,
+            None => self.read,
+        };
+
+        if timeout != self.current {
+            self.socket.set_read_timeout(timeout)?;
+            self.current = timeout;
+        }
+
+        Ok(())
+    }
+}
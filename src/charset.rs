@@ -0,0 +1,116 @@
+use std::borrow::Cow;
+use encoding_rs::Encoding;
+use crate::*;
+
+// Character set of a corpus as reported by COMMANDS::CORPUS_CHARSET. CQi
+// strings are sent in the encoding of the corpus they belong to, which is
+// only UTF-8 for corpora encoded with `-c utf8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    Utf8,
+    // ISO-8859-1, mapped byte for byte so that every string can be decoded
+    Latin1,
+    // the remaining 8 bit charsets known to CWB
+    Other(&'static Encoding),
+}
+
+impl Charset {
+    // Accepts the CWB names (ascii, latin1 ... latin9, cyrillic, arabic,
+    // greek, hebrew, utf8) and WHATWG encoding labels.
+    pub fn from_name(name: &str) -> Option<Charset> {
+        let name = name.trim().to_ascii_lowercase();
+
+        let encoding = match name.as_str() {
+            "utf8" | "utf-8" => return Some(Charset::Utf8),
+            // CWB uses ascii for "some 8 bit charset", so don't choke on it
+            "ascii" | "latin1" | "iso-8859-1" => return Some(Charset::Latin1),
+            "latin2" => encoding_rs::ISO_8859_2,
+            "latin3" => encoding_rs::ISO_8859_3,
+            "latin4" => encoding_rs::ISO_8859_4,
+            "cyrillic" => encoding_rs::ISO_8859_5,
+            "arabic" => encoding_rs::ISO_8859_6,
+            "greek" => encoding_rs::ISO_8859_7,
+            "hebrew" => encoding_rs::ISO_8859_8,
+            // ISO-8859-9 is decoded as its superset windows-1254 by encoding_rs
+            "latin5" => encoding_rs::WINDOWS_1254,
+            "latin6" => encoding_rs::ISO_8859_10,
+            "latin7" => encoding_rs::ISO_8859_13,
+            "latin8" => encoding_rs::ISO_8859_14,
+            "latin9" => encoding_rs::ISO_8859_15,
+            label => Encoding::for_label(label.as_bytes())?,
+        };
+
+        if encoding == encoding_rs::UTF_8 {
+            Some(Charset::Utf8)
+        } else {
+            Some(Charset::Other(encoding))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Charset::Utf8 => "utf8",
+            Charset::Latin1 => "latin1",
+            Charset::Other(encoding) => encoding.name(),
+        }
+    }
+
+    pub fn decode(&self, bytes: Vec<u8>) -> CQiResult<String> {
+        match self {
+            Charset::Utf8 => String::from_utf8(bytes)
+                .map_err(|_| CQiError::InvalidData("received string bytes are not utf8".to_string())),
+            Charset::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+            Charset::Other(encoding) => match encoding.decode_without_bom_handling_and_without_replacement(&bytes) {
+                Some(s) => Ok(s.into_owned()),
+                None => Err(CQiError::InvalidData(format!("received string bytes are not {}", encoding.name()))),
+            },
+        }
+    }
+
+    pub fn encode<'a>(&self, s: &'a str) -> CQiResult<Cow<'a, [u8]>> {
+        let unmappable = || CQiError::InvalidArgument(format!("\"{}\" can't be represented in {}", s, self.name()));
+
+        match self {
+            Charset::Utf8 => Ok(Cow::Borrowed(s.as_bytes())),
+            Charset::Latin1 => s.chars()
+                .map(|c| if (c as u32) < 0x100 { Ok(c as u8) } else { Err(unmappable()) })
+                .collect::<CQiResult<Vec<u8>>>()
+                .map(Cow::Owned),
+            Charset::Other(encoding) => match encoding.encode(s) {
+                (_, _, true) => Err(unmappable()),
+                (bytes, _, false) => Ok(bytes),
+            },
+        }
+    }
+}
+
+// A STRING exactly as it was received or is to be sent, for corpora whose
+// charset is unknown or that contain bytes invalid in their charset.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RawString(pub Vec<u8>);
+
+impl RawString {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for RawString {
+    fn from(bytes: Vec<u8>) -> RawString {
+        RawString(bytes)
+    }
+}
+
+impl From<&[u8]> for RawString {
+    fn from(bytes: &[u8]) -> RawString {
+        RawString(bytes.to_vec())
+    }
+}
+
+// The corpus a "CORPUS.attribute" or "CORPUS:Subcorpus" name refers to.
+pub(crate) fn corpus_of(name: &str) -> &str {
+    match name.find(['.', ':']) {
+        Some(i) => &name[..i],
+        None => name,
+    }
+}
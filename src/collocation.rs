@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use crate::matches::Pages;
+use crate::measures::{sort_by_score, Contingency, BATCH};
+use crate::*;
+
+// Collocates of the matches of a subcorpus, see Subcorpus::collocations().
+//
+//     let collocates = subcorpus.collocations(&lemma)
+//         .window(3, 3)
+//         .min_frequency(2)
+//         .sort_by(AssociationMeasure::LogRatio)
+//         .compute()?;
+//
+// The tokens within the window on either side of each match are counted
+// once, even where the windows of several matches overlap, and the matches
+// themselves are left out. Each candidate is scored on the window tokens
+// against the rest of the corpus.
+pub struct Collocations<'s, 'a, S: Read + Write> {
+    subcorpus: &'s Subcorpus<'a, S>,
+    attribute: PositionalAttribute<'a, S>,
+    left: INT,
+    right: INT,
+    min_frequency: INT,
+    sort_by: AssociationMeasure,
+    page_size: INT,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssociationMeasure {
+    LogLikelihood,
+    MutualInformation,
+    TScore,
+    Dice,
+    LogRatio,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collocate {
+    pub string: STRING,
+    pub id: INT,
+    // occurrences within the windows
+    pub frequency: INT,
+    // occurrences in the whole corpus
+    pub corpus_frequency: INT,
+    // frequency expected within the windows by chance
+    pub expected: f64,
+    pub log_likelihood: f64,
+    pub mutual_information: f64,
+    pub t_score: f64,
+    pub dice: f64,
+    pub log_ratio: f64,
+}
+
+impl Collocate {
+
+    pub fn score(&self, measure: AssociationMeasure) -> f64 {
+        match measure {
+            AssociationMeasure::LogLikelihood => self.log_likelihood,
+            AssociationMeasure::MutualInformation => self.mutual_information,
+            AssociationMeasure::TScore => self.t_score,
+            AssociationMeasure::Dice => self.dice,
+            AssociationMeasure::LogRatio => self.log_ratio,
+        }
+    }
+}
+
+impl<'s, 'a, S: Read + Write> Collocations<'s, 'a, S> {
+
+    pub(crate) fn new(subcorpus: &'s Subcorpus<'a, S>, attribute: &PositionalAttribute<'a, S>) -> Collocations<'s, 'a, S> {
+        Collocations {
+            subcorpus,
+            attribute: attribute.clone(),
+            left: 5,
+            right: 5,
+            min_frequency: 1,
+            sort_by: AssociationMeasure::LogLikelihood,
+            page_size: 10_000,
+        }
+    }
+
+    // tokens left and right of each match, 5 and 5 by default
+    pub fn window(mut self, left: INT, right: INT) -> Collocations<'s, 'a, S> {
+        self.left = left.max(0);
+        self.right = right.max(0);
+        self
+    }
+
+    // leaves out candidates seen less often within the windows
+    pub fn min_frequency(mut self, min_frequency: INT) -> Collocations<'s, 'a, S> {
+        self.min_frequency = min_frequency;
+        self
+    }
+
+    // highest score first, log-likelihood by default
+    pub fn sort_by(mut self, measure: AssociationMeasure) -> Collocations<'s, 'a, S> {
+        self.sort_by = measure;
+        self
+    }
+
+    // matches dumped per request
+    pub fn page_size(mut self, page_size: INT) -> Collocations<'s, 'a, S> {
+        self.page_size = page_size;
+        self
+    }
+
+    pub fn compute(&self) -> CQiResult<Vec<Collocate>> {
+        let corpus_size = self.attribute.size()?;
+
+        // Windows and match spans are half-open ranges. Matches come sorted by
+        // start, so nothing after a page reaches back before its last start
+        // minus the left window: the window up to there is counted and the
+        // rest is carried over to the next page.
+        let mut windows = Vec::new();
+        let mut spans = Vec::new();
+        let mut counts = HashMap::new();
+        let mut window_size = 0;
+        let mut pages = Pages::new(self.subcorpus.size()?, self.page_size)?.peekable();
+        while let Some((first, last)) = pages.next() {
+            let starts = self.subcorpus.dump(FIELD::MATCH, first, last)?;
+            let ends = self.subcorpus.dump(FIELD::MATCHEND, first, last)?;
+            for (&start, &end) in starts.iter().zip(&ends) {
+                let after = end.saturating_add(1);
+                windows.push((start.saturating_sub(self.left).max(0), start));
+                windows.push((after, after.saturating_add(self.right).min(corpus_size)));
+                spans.push((start, after));
+            }
+
+            let done = match (pages.peek(), starts.last()) {
+                (Some(_), Some(&start)) => start.saturating_sub(self.left),
+                _ => INT::MAX,
+            };
+            spans = merge(spans);
+            let mut cpos = Vec::new();
+            let mut later = Vec::new();
+            for (from, to) in subtract(&merge(windows), &spans) {
+                if from < done {
+                    cpos.extend(from..to.min(done));
+                }
+                if to > done {
+                    later.push((from.max(done), to));
+                }
+            }
+            windows = later;
+            spans.retain(|&(_, to)| to > done);
+
+            window_size += cpos.len();
+            for chunk in cpos.chunks(BATCH) {
+                for id in self.attribute.cpos2id(chunk)? {
+                    *counts.entry(id).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut candidates: Vec<(INT, INT)> = counts.into_iter()
+            .filter(|&(id, frequency)| id >= 0 && frequency >= self.min_frequency)
+            .collect();
+        candidates.sort_unstable();
+        let ids: Vec<INT> = candidates.iter().map(|c| c.0).collect();
+        let corpus_frequencies = self.attribute.id2freq(&ids)?;
+        let strings = self.attribute.id2str(&ids)?;
+
+        let window_size = window_size as f64;
+        let mut collocates: Vec<Collocate> = candidates.into_iter().zip(corpus_frequencies).zip(strings)
+            .map(|(((id, frequency), corpus_frequency), string)| {
+                let table = Contingency::new(
+                    frequency as f64,
+                    (corpus_frequency - frequency).max(0) as f64,
+                    window_size,
+                    corpus_size as f64 - window_size,
+                );
+
+                Collocate {
+                    string,
+                    id,
+                    frequency,
+                    corpus_frequency,
+                    expected: table.expected(),
+                    log_likelihood: table.log_likelihood(),
+                    mutual_information: table.mutual_information(),
+                    t_score: table.t_score(),
+                    dice: table.dice(),
+                    log_ratio: table.log_ratio(),
+                }
+            })
+            .collect();
+
+        sort_by_score(&mut collocates, |c| c.score(self.sort_by), |c| &c.string);
+        Ok(collocates)
+    }
+}
+
+// Sorts the half-open <ranges> and joins those that overlap or touch.
+fn merge(mut ranges: Vec<(INT, INT)>) -> Vec<(INT, INT)> {
+    ranges.retain(|&(from, to)| from < to);
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(INT, INT)> = Vec::with_capacity(ranges.len());
+    for (from, to) in ranges {
+        match merged.last_mut() {
+            Some(last) if from <= last.1 => last.1 = last.1.max(to),
+            _ => merged.push((from, to)),
+        }
+    }
+    merged
+}
+
+// The parts of <ranges> outside of <holes>, both merged.
+fn subtract(ranges: &[(INT, INT)], holes: &[(INT, INT)]) -> Vec<(INT, INT)> {
+    let mut rest = Vec::with_capacity(ranges.len());
+    let mut holes = holes.iter().peekable();
+    for &(mut from, to) in ranges {
+        while let Some(&&(hole_from, hole_to)) = holes.peek() {
+            if hole_from >= to {
+                break;
+            }
+            if hole_from > from {
+                rest.push((from, hole_from));
+            }
+            from = from.max(hole_to);
+            if hole_to > to {
+                break;
+            }
+            holes.next();
+        }
+        if from < to {
+            rest.push((from, to));
+        }
+    }
+    rest
+}
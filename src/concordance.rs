@@ -0,0 +1,202 @@
+use std::io::{Read, Write};
+use std::vec;
+use crate::decode::check_reply_len;
+use crate::matches::Pages;
+use crate::*;
+
+// Keyword-in-context lines of a subcorpus, see Subcorpus::concordance().
+//
+//     let lines = subcorpus.concordance()
+//         .attributes(&["word", "pos"])
+//         .left(Context::Tokens(5))
+//         .right(Context::Region("s".to_string()))
+//         .lines(0, 99)?;
+//
+// The strings of all lines are fetched with one CL_CPOS2STR per attribute.
+pub struct Concordance<'s, 'a, S: Read + Write> {
+    subcorpus: &'s Subcorpus<'a, S>,
+    attributes: Vec<String>,
+    left: Context,
+    right: Context,
+}
+
+// How far the context of a match reaches on one side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Context {
+    Tokens(INT),
+    // up to the boundary of the region of this s-attribute the match starts
+    // (left) or ends (right) in, or no context outside of regions
+    Region(String),
+}
+
+// One token is the list of its strings in the order of the attributes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConcordanceLine {
+    pub match_start: INT,
+    pub match_end: INT,
+    pub left: Vec<STRING_LIST>,
+    pub keyword: Vec<STRING_LIST>,
+    pub right: Vec<STRING_LIST>,
+}
+
+impl<'s, 'a, S: Read + Write> Concordance<'s, 'a, S> {
+
+    pub(crate) fn new(subcorpus: &'s Subcorpus<'a, S>) -> Concordance<'s, 'a, S> {
+        Concordance {
+            subcorpus,
+            attributes: vec!["word".to_string()],
+            left: Context::Tokens(5),
+            right: Context::Tokens(5),
+        }
+    }
+
+    // positional attributes of the mother corpus, "word" by default
+    pub fn attributes(mut self, attributes: &[&str]) -> Concordance<'s, 'a, S> {
+        self.attributes = attributes.iter().map(|a| a.to_string()).collect();
+        self
+    }
+
+    // both sides, 5 tokens by default
+    pub fn context(self, context: Context) -> Concordance<'s, 'a, S> {
+        self.left(context.clone()).right(context)
+    }
+
+    pub fn left(mut self, context: Context) -> Concordance<'s, 'a, S> {
+        self.left = context;
+        self
+    }
+
+    pub fn right(mut self, context: Context) -> Concordance<'s, 'a, S> {
+        self.right = context;
+        self
+    }
+
+    // every match of the subcorpus
+    pub fn all_lines(&self) -> CQiResult<Vec<ConcordanceLine>> {
+        match self.subcorpus.size()? {
+            0 => Ok(Vec::new()),
+            size => self.lines(0, size - 1),
+        }
+    }
+
+    // Every match of the subcorpus, fetched <page_size> lines at a time so
+    // that only one page is held in memory.
+    pub fn paged(self, page_size: INT) -> CQiResult<ConcordanceLines<'s, 'a, S>> {
+        Ok(ConcordanceLines {
+            pages: Pages::new(self.subcorpus.size()?, page_size)?,
+            concordance: self,
+            page: Vec::new().into_iter(),
+        })
+    }
+
+    // the matches <first> to <last>, both inclusive
+    pub fn lines(&self, first: INT, last: INT) -> CQiResult<Vec<ConcordanceLine>> {
+        let starts = self.subcorpus.dump(FIELD::MATCH, first, last)?;
+        let ends = self.subcorpus.dump(FIELD::MATCHEND, first, last)?;
+        self.lines_for(&starts, &ends)
+    }
+
+    // Lines for matches given by their start and end positions, e.g. from an
+    // earlier dump.
+    pub fn lines_for(&self, starts: &[INT], ends: &[INT]) -> CQiResult<Vec<ConcordanceLine>> {
+        if starts.len() != ends.len() {
+            return Err(CQiError::InvalidArgument(format!("{} match starts but {} match ends", starts.len(), ends.len())));
+        }
+        if self.attributes.is_empty() {
+            return Err(CQiError::InvalidArgument("no attributes selected for the concordance".to_string()));
+        }
+        if starts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let connection = self.subcorpus.connection();
+        let specifiers: Vec<String> = self.attributes.iter().map(|a| self.specifier(a)).collect();
+        let last_cpos = connection.cl_attribute_size(&specifiers[0])? - 1;
+
+        let lbounds = match &self.left {
+            Context::Tokens(n) => starts.iter().map(|&start| start.saturating_sub((*n).max(0)).max(0)).collect(),
+            Context::Region(s) => or_own(connection.cl_cpos2lbound(&self.specifier(s), starts)?, starts, "CL_CPOS2LBOUND")?,
+        };
+        let rbounds = match &self.right {
+            Context::Tokens(n) => ends.iter().map(|&end| end.saturating_add((*n).max(0)).min(last_cpos)).collect(),
+            Context::Region(s) => or_own(connection.cl_cpos2rbound(&self.specifier(s), ends)?, ends, "CL_CPOS2RBOUND")?,
+        };
+
+        // the spans of all lines end to end, so every attribute is one request
+        let mut spans = Vec::with_capacity(starts.len());
+        let mut cpos = Vec::new();
+        for i in 0..starts.len() {
+            if starts[i] < 0 || ends[i] < starts[i] {
+                return Err(CQiError::InvalidArgument(format!("invalid match range {}..{}", starts[i], ends[i])));
+            }
+            let (lbound, rbound) = (lbounds[i].min(starts[i]), rbounds[i].max(ends[i]));
+            spans.push((cpos.len(), lbound, rbound));
+            cpos.extend(lbound..=rbound);
+        }
+
+        let strings = specifiers.iter()
+            .map(|specifier| {
+                let strings = connection.cl_cpos2str(specifier, &cpos)?;
+                check_reply_len(&strings, cpos.len(), "CL_CPOS2STR")?;
+                Ok(strings)
+            })
+            .collect::<CQiResult<Vec<_>>>()?;
+        let tokens = |from: usize, to: usize| -> Vec<STRING_LIST> {
+            (from..to).map(|i| strings.iter().map(|s| s[i].clone()).collect()).collect()
+        };
+
+        Ok(spans.into_iter().enumerate().map(|(i, (offset, lbound, rbound))| {
+            let start = offset + (starts[i] - lbound) as usize;
+            let end = offset + (ends[i] - lbound) as usize + 1;
+            let past = offset + (rbound - lbound) as usize + 1;
+
+            ConcordanceLine {
+                match_start: starts[i],
+                match_end: ends[i],
+                left: tokens(offset, start),
+                keyword: tokens(start, end),
+                right: tokens(end, past),
+            }
+        }).collect())
+    }
+
+    fn specifier(&self, attribute: &str) -> String {
+        format!("{}.{}", self.subcorpus.mother(), attribute)
+    }
+}
+
+// Iterator returned by Concordance::paged(), ends after the first error.
+pub struct ConcordanceLines<'s, 'a, S: Read + Write> {
+    concordance: Concordance<'s, 'a, S>,
+    pages: Pages,
+    page: vec::IntoIter<ConcordanceLine>,
+}
+
+impl<S: Read + Write> Iterator for ConcordanceLines<'_, '_, S> {
+    type Item = CQiResult<ConcordanceLine>;
+
+    fn next(&mut self) -> Option<CQiResult<ConcordanceLine>> {
+        if let Some(line) = self.page.next() {
+            return Some(Ok(line));
+        }
+
+        let (first, last) = self.pages.next()?;
+        match self.concordance.lines(first, last) {
+            Ok(page) => {
+                self.page = page.into_iter();
+                self.page.next().map(Ok)
+            },
+            Err(e) => {
+                self.pages.stop();
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+// Positions outside of any region of the s-attribute (bound -1) are their own
+// boundary. <bounds> is the reply of <command> for <cpos>.
+fn or_own(bounds: INT_LIST, cpos: &[INT], command: &str) -> CQiResult<INT_LIST> {
+    check_reply_len(&bounds, cpos.len(), command)?;
+    Ok(bounds.into_iter().zip(cpos).map(|(bound, &cpos)| if bound < 0 { cpos } else { bound }).collect())
+}
@@ -0,0 +1,146 @@
+use std::cell::OnceCell;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use crate::*;
+
+// Handle on one corpus of a connection, see CQiConnection::corpus(). The
+// metadata is asked from the server on first use and kept for the lifetime
+// of the handle, so it's cheap to call the getters repeatedly.
+pub struct Corpus<'a, S: Read + Write = TcpStream> {
+    connection: &'a CQiConnection<S>,
+    name: String,
+    properties: OnceCell<STRING_LIST>,
+    full_name: OnceCell<STRING>,
+    info: OnceCell<STRING_LIST>,
+    p_attributes: OnceCell<STRING_LIST>,
+    s_attributes: OnceCell<STRING_LIST>,
+    a_attributes: OnceCell<STRING_LIST>,
+    size: OnceCell<INT>,
+}
+
+impl<'a, S: Read + Write> Corpus<'a, S> {
+
+    pub(crate) fn new(connection: &'a CQiConnection<S>, name: &str) -> Corpus<'a, S> {
+        Corpus {
+            connection,
+            name: name.to_owned(),
+            properties: OnceCell::new(),
+            full_name: OnceCell::new(),
+            info: OnceCell::new(),
+            p_attributes: OnceCell::new(),
+            s_attributes: OnceCell::new(),
+            a_attributes: OnceCell::new(),
+            size: OnceCell::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn connection(&self) -> &'a CQiConnection<S> {
+        self.connection
+    }
+
+    // cached by the connection, so it's shared with the attribute handles
+    pub fn charset(&self) -> CQiResult<Charset> {
+        self.connection.charset(&self.name)
+    }
+
+    pub fn properties(&self) -> CQiResult<&[STRING]> {
+        cached(&self.properties, || self.connection.corpus_properties(&self.name)).map(Vec::as_slice)
+    }
+
+    pub fn full_name(&self) -> CQiResult<&str> {
+        cached(&self.full_name, || self.connection.corpus_full_name(&self.name)).map(String::as_str)
+    }
+
+    pub fn info(&self) -> CQiResult<&[STRING]> {
+        cached(&self.info, || self.connection.corpus_info(&self.name)).map(Vec::as_slice)
+    }
+
+    pub fn positional_attributes(&self) -> CQiResult<&[STRING]> {
+        cached(&self.p_attributes, || self.connection.corpus_positional_attributes(&self.name)).map(Vec::as_slice)
+    }
+
+    pub fn structural_attributes(&self) -> CQiResult<&[STRING]> {
+        cached(&self.s_attributes, || self.connection.corpus_structural_attributes(&self.name)).map(Vec::as_slice)
+    }
+
+    pub fn alignment_attributes(&self) -> CQiResult<&[STRING]> {
+        cached(&self.a_attributes, || self.connection.corpus_alignment_attributes(&self.name)).map(Vec::as_slice)
+    }
+
+    // number of tokens, i.e. the size of the word attribute every corpus has
+    pub fn size(&self) -> CQiResult<INT> {
+        cached(&self.size, || self.connection.cl_attribute_size(&self.specifier("word"))).copied()
+    }
+
+    // "CORPUS.attribute" for use with the CL_* commands
+    pub fn specifier(&self, attribute: &str) -> String {
+        format!("{}.{}", self.name, attribute)
+    }
+
+    pub fn p_attribute(&self, name: &str) -> CQiResult<PositionalAttribute<'a, S>> {
+        self.check_attribute(self.positional_attributes()?, "positional", name)?;
+        Ok(PositionalAttribute::new(self.connection, self.specifier(name)))
+    }
+
+    pub fn s_attribute(&self, name: &str) -> CQiResult<StructuralAttribute<'a, S>> {
+        self.check_attribute(self.structural_attributes()?, "structural", name)?;
+        Ok(StructuralAttribute::new(self.connection, self.specifier(name)))
+    }
+
+    pub fn a_attribute(&self, name: &str) -> CQiResult<AlignmentAttribute<'a, S>> {
+        self.check_attribute(self.alignment_attributes()?, "alignment", name)?;
+        Ok(AlignmentAttribute::new(self.connection, self.specifier(name)))
+    }
+
+    // Runs <query> into the subcorpus <name>, which is dropped on the server
+    // together with the returned handle unless it's persisted.
+    pub fn query(&self, name: &str, query: &str) -> CQiResult<Subcorpus<'a, S>> {
+        self.connection.cqp_query(&self.name, name, query)?;
+        Ok(Subcorpus::new(self.connection, &self.name, name, true))
+    }
+
+    // Like query(), with a fresh name from CQiConnection::unique_subcorpus_name().
+    pub fn query_unnamed(&self, query: &str) -> CQiResult<Subcorpus<'a, S>> {
+        let name = self.connection.unique_subcorpus_name(&self.name)?;
+        self.query(&name, query)
+    }
+
+    // Handle on an existing subcorpus, which is left alone when released.
+    pub fn subcorpus(&self, name: &str) -> Subcorpus<'a, S> {
+        Subcorpus::new(self.connection, &self.name, name, false)
+    }
+
+    // names of the subcorpora currently defined on the server
+    pub fn subcorpora(&self) -> CQiResult<STRING_LIST> {
+        self.connection.cqp_list_subcorpora(&self.name)
+    }
+
+    fn check_attribute(&self, attributes: &[STRING], kind: &str, name: &str) -> CQiResult<()> {
+        if attributes.iter().any(|a| a == name) {
+            Ok(())
+        } else {
+            Err(CQiError::InvalidArgument(format!("corpus {} has no {} attribute {}", self.name, kind, name)))
+        }
+    }
+}
+
+impl<S: Read + Write> fmt::Debug for Corpus<'_, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Corpus({})", self.name)
+    }
+}
+
+// OnceCell::get_or_try_init() without the nightly feature
+pub(crate) fn cached<T>(cell: &OnceCell<T>, fetch: impl FnOnce() -> CQiResult<T>) -> CQiResult<&T> {
+    if let Some(value) = cell.get() {
+        return Ok(value);
+    }
+
+    let value = fetch()?;
+    Ok(cell.get_or_init(|| value))
+}
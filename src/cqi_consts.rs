@@ -14,7 +14,7 @@ pub const PAD: u8 =  0x00;
 //  ***   CQi responses
 //  ***
 
-#[derive(Debug, enum_utils::FromStr, num_derive::FromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, enum_utils::FromStr, num_derive::FromPrimitive)]
 #[repr(u8)]
 pub enum ResponseType {
     STATUS = 0x01,
@@ -24,7 +24,7 @@ pub enum ResponseType {
     CQP_ERROR = 0x05,
 }
 
-#[derive(Debug, enum_utils::FromStr, num_derive::FromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, enum_utils::FromStr, num_derive::FromPrimitive)]
 #[repr(u16)]
 pub enum STATUS {
     OK = 0x0101,
@@ -33,7 +33,7 @@ pub enum STATUS {
     PING_OK = 0x0104,
 }
 
-#[derive(Debug, enum_utils::FromStr, num_derive::FromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, enum_utils::FromStr, num_derive::FromPrimitive)]
 #[repr(u16)]
 pub enum ERROR {
     GENERAL_ERROR = 0x0201,
@@ -43,7 +43,7 @@ pub enum ERROR {
     // includes corpus/attribute/subcorpus specifier syntax
 }
 
-#[derive(Debug, enum_utils::FromStr, num_derive::FromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, enum_utils::FromStr, num_derive::FromPrimitive)]
 #[repr(u16)]
 pub enum DATA {
     BYTE = 0x0301,
@@ -59,7 +59,7 @@ pub enum DATA {
     INT_TABLE = 0x030B,
 }
 
-#[derive(Debug, enum_utils::FromStr, num_derive::FromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, enum_utils::FromStr, num_derive::FromPrimitive)]
 #[repr(u16)]
 pub enum CL_ERROR {
     NO_SUCH_ATTRIBUTE = 0x0401,
@@ -83,7 +83,7 @@ pub enum CL_ERROR {
     // try discarding some other corpora and/or subcorpora
 }
 
-#[derive(Debug, enum_utils::FromStr, num_derive::FromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, enum_utils::FromStr, num_derive::FromPrimitive)]
 #[repr(u16)]
 pub enum CQP_ERROR {
     GENERAL = 0x0501,
@@ -96,7 +96,7 @@ pub enum CQP_ERROR {
 //  ***   CQi commands
 //  ***
 
-#[derive(Debug, enum_utils::FromStr, num_derive::FromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, enum_utils::FromStr, num_derive::FromPrimitive)]
 #[repr(u16)]
 pub enum COMMANDS {
     // CTRL = 0x1100,
@@ -370,6 +370,30 @@ pub const FIELD_TARGET_9: u8 = 0x09;
 pub const FIELD_TARGET: u8 = 0x00;
 pub const FIELD_KEYWORD: u8 = 0x09;
 
+// Typed version of the FIELD_* constants, accepted by the CQP_* subcorpus
+// commands instead of a raw BYTE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, enum_utils::FromStr, num_derive::FromPrimitive)]
+#[repr(u8)]
+pub enum FIELD {
+    MATCH = FIELD_MATCH,
+    MATCHEND = FIELD_MATCHEND,
+    TARGET_0 = FIELD_TARGET_0,
+    TARGET_1 = FIELD_TARGET_1,
+    TARGET_2 = FIELD_TARGET_2,
+    TARGET_3 = FIELD_TARGET_3,
+    TARGET_4 = FIELD_TARGET_4,
+    TARGET_5 = FIELD_TARGET_5,
+    TARGET_6 = FIELD_TARGET_6,
+    TARGET_7 = FIELD_TARGET_7,
+    TARGET_8 = FIELD_TARGET_8,
+    TARGET_9 = FIELD_TARGET_9,
+}
+
+impl FIELD {
+    pub const TARGET: FIELD = FIELD::TARGET_0;
+    pub const KEYWORD: FIELD = FIELD::TARGET_9;
+}
+
 
 // CQi version is CQI_MAJOR_VERSION.CQI_MINOR_VERSION
 pub const CQI_MAJOR_VERSION: u8 = 0x00;
@@ -0,0 +1,288 @@
+use std::io::{Read, Write};
+use byteorder::{NetworkEndian, ReadBytesExt};
+use crate::*;
+
+// Upper bounds for the length prefixes of received data. Lengths come from
+// the server and are never trusted for allocations, but a corrupt stream
+// should fail early instead of being read until it runs dry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    // bytes per STRING
+    pub max_string_len: usize,
+    // elements per *_LIST
+    pub max_list_len: usize,
+    // rows * columns per INT_TABLE
+    pub max_table_cells: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> DecodeLimits {
+        DecodeLimits {
+            max_string_len: WORD::MAX as usize,
+            max_list_len: 1 << 28,
+            max_table_cells: 1 << 28,
+        }
+    }
+}
+
+// elements reserved up front, anything beyond that grows with the data
+const PREALLOC: usize = 4096;
+
+// Validates a length prefix against its limit.
+pub(crate) fn check_len(len: INT, limit: usize, what: &'static str) -> CQiResult<usize> {
+    if len < 0 {
+        return Err(CQiError::InvalidData(format!("negative {} length {}", what, len)));
+    }
+
+    let len = len as usize;
+
+    if len > limit {
+        return Err(CQiError::LimitExceeded { what, len, limit });
+    }
+
+    Ok(len)
+}
+
+pub(crate) fn check_table(rows: INT, cols: INT, limits: &DecodeLimits) -> CQiResult<(usize, usize)> {
+    let rows = check_len(rows, limits.max_table_cells, "table")?;
+    let cols = check_len(cols, limits.max_table_cells, "table")?;
+
+    match rows.checked_mul(cols) {
+        Some(cells) if cells <= limits.max_table_cells => Ok((rows, cols)),
+        _ => Err(CQiError::LimitExceeded { what: "table", len: rows.saturating_mul(cols), limit: limits.max_table_cells }),
+    }
+}
+
+// Decoding counterpart of CQiData: reads a value of the implementing type
+// from the stream, without the DATA header in front of it.
+pub trait CQiDecode: Sized {
+    fn read_cqi_bytes(stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<Self>;
+}
+
+impl CQiDecode for BOOL {
+    fn read_cqi_bytes(stream: &mut dyn Read, _limits: &DecodeLimits) -> CQiResult<Self> {
+        Ok(stream.read_u8()? > 0)
+    }
+}
+
+impl CQiDecode for BYTE {
+    fn read_cqi_bytes(stream: &mut dyn Read, _limits: &DecodeLimits) -> CQiResult<Self> {
+        Ok(stream.read_u8()?)
+    }
+}
+
+impl CQiDecode for WORD {
+    fn read_cqi_bytes(stream: &mut dyn Read, _limits: &DecodeLimits) -> CQiResult<Self> {
+        Ok(stream.read_u16::<NetworkEndian>()?)
+    }
+}
+
+impl CQiDecode for INT {
+    fn read_cqi_bytes(stream: &mut dyn Read, _limits: &DecodeLimits) -> CQiResult<Self> {
+        Ok(stream.read_i32::<NetworkEndian>()?)
+    }
+}
+
+impl CQiDecode for STRING {
+    fn read_cqi_bytes(stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<Self> {
+        Charset::Utf8.decode(RawString::read_cqi_bytes(stream, limits)?.0)
+    }
+}
+
+impl CQiDecode for RawString {
+    fn read_cqi_bytes(stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<Self> {
+        let len = WORD::read_cqi_bytes(stream, limits)?;
+
+        if len as usize > limits.max_string_len {
+            return Err(CQiError::LimitExceeded { what: "string", len: len as usize, limit: limits.max_string_len });
+        }
+
+        let mut data = vec![0; len as usize];
+        stream.read_exact(&mut data)?;
+
+        Ok(RawString(data))
+    }
+}
+
+impl CQiDecode for BOOL_LIST {
+    fn read_cqi_bytes(stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<Self> {
+        read_cqi_list(stream, limits)
+    }
+}
+
+impl CQiDecode for BYTE_LIST {
+    fn read_cqi_bytes(stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<Self> {
+        read_cqi_list(stream, limits)
+    }
+}
+
+impl CQiDecode for INT_LIST {
+    fn read_cqi_bytes(stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<Self> {
+        read_cqi_list(stream, limits)
+    }
+}
+
+impl CQiDecode for STRING_LIST {
+    fn read_cqi_bytes(stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<Self> {
+        read_cqi_list(stream, limits)
+    }
+}
+
+impl CQiDecode for Vec<RawString> {
+    fn read_cqi_bytes(stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<Self> {
+        read_cqi_list(stream, limits)
+    }
+}
+
+impl CQiDecode for INT_INT {
+    fn read_cqi_bytes(stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<Self> {
+        Ok(
+            [
+                INT::read_cqi_bytes(stream, limits)?,
+                INT::read_cqi_bytes(stream, limits)?,
+            ]
+        )
+    }
+}
+
+impl CQiDecode for INT_INT_INT_INT {
+    fn read_cqi_bytes(stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<Self> {
+        Ok(
+            [
+                INT::read_cqi_bytes(stream, limits)?,
+                INT::read_cqi_bytes(stream, limits)?,
+                INT::read_cqi_bytes(stream, limits)?,
+                INT::read_cqi_bytes(stream, limits)?,
+            ]
+        )
+    }
+}
+
+impl CQiDecode for INT_TABLE {
+    fn read_cqi_bytes(stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<Self> {
+        let rows = INT::read_cqi_bytes(stream, limits)?;
+        let cols = INT::read_cqi_bytes(stream, limits)?;
+        let (rows, cols) = check_table(rows, cols, limits)?;
+
+        let mut data: INT_TABLE = Vec::with_capacity(rows.min(PREALLOC));
+
+        for _ in 0..rows {
+            data.push(read_cqi_multiple(stream, cols, limits)?);
+        }
+
+        Ok(data)
+    }
+}
+
+fn read_cqi_list<T: CQiDecode>(stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<Vec<T>> {
+    let len = INT::read_cqi_bytes(stream, limits)?;
+    let len = check_len(len, limits.max_list_len, "list")?;
+    read_cqi_multiple(stream, len, limits)
+}
+
+fn read_cqi_multiple<T: CQiDecode>(stream: &mut dyn Read, len: usize, limits: &DecodeLimits) -> CQiResult<Vec<T>> {
+    let mut data = Vec::with_capacity(len.min(PREALLOC));
+
+    for _ in 0..len {
+        data.push(T::read_cqi_bytes(stream, limits)?);
+    }
+
+    Ok(data)
+}
+
+// Any value that can follow a DATA header, for consumers that only learn
+// the type of a response at runtime.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CQiValue {
+    Byte(BYTE),
+    Bool(BOOL),
+    Int(INT),
+    String(STRING),
+    ByteList(BYTE_LIST),
+    BoolList(BOOL_LIST),
+    IntList(INT_LIST),
+    StringList(STRING_LIST),
+    IntInt(INT_INT),
+    IntIntIntInt(INT_INT_INT_INT),
+    IntTable(INT_TABLE),
+}
+
+// Checks that a list received for a request has one value per requested
+// item, so that the two can be lined up by index.
+pub(crate) fn check_reply_len<T>(reply: &[T], expected: usize, what: &str) -> CQiResult<()> {
+    if reply.len() != expected {
+        return Err(CQiError::InvalidData(format!("expected {} values of {}, got {}", expected, what, reply.len())));
+    }
+    Ok(())
+}
+
+// Reads past a value nobody asked for, so that the next response is read
+// from its start. Strings are skipped without decoding them.
+pub(crate) fn skip_value(datatype: DATA, stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<()> {
+    match datatype {
+        DATA::STRING => RawString::read_cqi_bytes(stream, limits).map(drop),
+        DATA::STRING_LIST => Vec::<RawString>::read_cqi_bytes(stream, limits).map(drop),
+        _ => CQiValue::read_cqi_value(datatype, stream, limits).map(drop),
+    }
+}
+
+impl CQiValue {
+    pub fn read_cqi_value(datatype: DATA, stream: &mut dyn Read, limits: &DecodeLimits) -> CQiResult<CQiValue> {
+        Ok(match datatype {
+            DATA::BYTE => CQiValue::Byte(CQiDecode::read_cqi_bytes(stream, limits)?),
+            DATA::BOOL => CQiValue::Bool(CQiDecode::read_cqi_bytes(stream, limits)?),
+            DATA::INT => CQiValue::Int(CQiDecode::read_cqi_bytes(stream, limits)?),
+            DATA::STRING => CQiValue::String(CQiDecode::read_cqi_bytes(stream, limits)?),
+            DATA::BYTE_LIST => CQiValue::ByteList(CQiDecode::read_cqi_bytes(stream, limits)?),
+            DATA::BOOL_LIST => CQiValue::BoolList(CQiDecode::read_cqi_bytes(stream, limits)?),
+            DATA::INT_LIST => CQiValue::IntList(CQiDecode::read_cqi_bytes(stream, limits)?),
+            DATA::STRING_LIST => CQiValue::StringList(CQiDecode::read_cqi_bytes(stream, limits)?),
+            DATA::INT_INT => CQiValue::IntInt(CQiDecode::read_cqi_bytes(stream, limits)?),
+            DATA::INT_INT_INT_INT => CQiValue::IntIntIntInt(CQiDecode::read_cqi_bytes(stream, limits)?),
+            DATA::INT_TABLE => CQiValue::IntTable(CQiDecode::read_cqi_bytes(stream, limits)?),
+        })
+    }
+
+    pub fn data_type(&self) -> DATA {
+        match self {
+            CQiValue::Byte(_) => DATA::BYTE,
+            CQiValue::Bool(_) => DATA::BOOL,
+            CQiValue::Int(_) => DATA::INT,
+            CQiValue::String(_) => DATA::STRING,
+            CQiValue::ByteList(_) => DATA::BYTE_LIST,
+            CQiValue::BoolList(_) => DATA::BOOL_LIST,
+            CQiValue::IntList(_) => DATA::INT_LIST,
+            CQiValue::StringList(_) => DATA::STRING_LIST,
+            CQiValue::IntInt(_) => DATA::INT_INT,
+            CQiValue::IntIntIntInt(_) => DATA::INT_INT_INT_INT,
+            CQiValue::IntTable(_) => DATA::INT_TABLE,
+        }
+    }
+
+    fn inner(&self) -> &dyn CQiData {
+        match self {
+            CQiValue::Byte(x) => x,
+            CQiValue::Bool(x) => x,
+            CQiValue::Int(x) => x,
+            CQiValue::String(x) => x,
+            CQiValue::ByteList(x) => x,
+            CQiValue::BoolList(x) => x,
+            CQiValue::IntList(x) => x,
+            CQiValue::StringList(x) => x,
+            CQiValue::IntInt(x) => x,
+            CQiValue::IntIntIntInt(x) => x,
+            CQiValue::IntTable(x) => x,
+        }
+    }
+}
+
+// Writes the bare value, the DATA header is up to the caller.
+impl CQiData for CQiValue {
+    fn repr(&self) -> String {
+        self.inner().repr()
+    }
+
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
+        self.inner().write_cqi_bytes(stream)
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use crate::matches::Pages;
+use crate::*;
+
+// Hits of a subcorpus within the regions of one value of an s-attribute,
+// see Subcorpus::distribution().
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueDistribution {
+    pub value: STRING,
+    pub hits: INT,
+    // regions with this value and the tokens they span
+    pub regions: INT,
+    pub tokens: INT,
+    // hits per million tokens of these regions
+    pub per_million: f64,
+}
+
+// How evenly the occurrences of a lexicon item are spread over the regions
+// of an s-attribute (the parts), see PositionalAttribute::dispersion(). For
+// items without occurrences D and ARF are 0 and DP is 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dispersion {
+    // occurrences in the whole corpus
+    pub frequency: INT,
+    // parts the item occurs in
+    pub range: INT,
+    // Juilland's D on the frequencies relative to part size, 1 for perfectly
+    // even
+    pub juilland_d: f64,
+    // Gries' DP, 0 for perfectly even and approaching 1 for uneven
+    pub gries_dp: f64,
+    // average reduced frequency (Savický & Hlaváčová) over the whole corpus,
+    // between 1 and the frequency
+    pub arf: f64,
+}
+
+// matches dumped per request
+const PAGE_SIZE: INT = 10_000;
+
+// Matches are counted for the region their first token is in, matches
+// outside of any region are left out.
+pub(crate) fn distribution<S: Read + Write>(subcorpus: &Subcorpus<S>, attribute: &StructuralAttribute<S>) -> CQiResult<Vec<ValueDistribution>> {
+    let regions = attribute.regions()?;
+    let strucs: Vec<INT> = (0..regions.len() as INT).collect();
+    let values = attribute.struc2str(&strucs)?;
+
+    let mut hits = vec![0; regions.len()];
+    for (first, last) in Pages::new(subcorpus.size()?, PAGE_SIZE)? {
+        let starts = subcorpus.dump(FIELD::MATCH, first, last)?;
+        for struc in attribute.cpos2struc(&starts)? {
+            if let Some(hits) = usize::try_from(struc).ok().and_then(|struc| hits.get_mut(struc)) {
+                *hits += 1;
+            }
+        }
+    }
+
+    let mut by_value: BTreeMap<STRING, ValueDistribution> = BTreeMap::new();
+    for ((value, &[start, end]), hits) in values.into_iter().zip(regions).zip(hits) {
+        let entry = by_value.entry(value.clone()).or_insert(ValueDistribution {
+            value,
+            hits: 0,
+            regions: 0,
+            tokens: 0,
+            per_million: 0.0,
+        });
+        entry.hits += hits;
+        entry.regions += 1;
+        entry.tokens += end - start + 1;
+    }
+
+    Ok(by_value.into_values().map(|mut v| {
+        if v.tokens > 0 {
+            v.per_million = v.hits as f64 * 1_000_000.0 / v.tokens as f64;
+        }
+        v
+    }).collect())
+}
+
+pub(crate) fn dispersion<S: Read + Write>(attribute: &PositionalAttribute<S>, ids: &[INT], parts: &StructuralAttribute<S>) -> CQiResult<Vec<Dispersion>> {
+    let parts = Parts::new(parts)?;
+    let corpus_size = attribute.size()?;
+
+    ids.iter().map(|&id| parts.dispersion(&attribute.id2cpos(id)?, corpus_size)).collect()
+}
+
+pub(crate) fn group_dispersion<S: Read + Write>(attribute: &PositionalAttribute<S>, ids: &[INT], parts: &StructuralAttribute<S>) -> CQiResult<Dispersion> {
+    let parts = Parts::new(parts)?;
+    let mut cpos = attribute.idlist2cpos(ids)?;
+    cpos.sort_unstable();
+
+    parts.dispersion(&cpos, attribute.size()?)
+}
+
+struct Parts<'p, 'a, S: Read + Write> {
+    attribute: &'p StructuralAttribute<'a, S>,
+    sizes: Vec<INT>,
+    total: INT,
+}
+
+impl<'p, 'a, S: Read + Write> Parts<'p, 'a, S> {
+
+    fn new(attribute: &'p StructuralAttribute<'a, S>) -> CQiResult<Parts<'p, 'a, S>> {
+        let sizes: Vec<INT> = attribute.regions()?.iter().map(|[start, end]| end - start + 1).collect();
+
+        if sizes.is_empty() {
+            return Err(CQiError::InvalidArgument(format!("structural attribute {} has no regions", attribute.specifier())));
+        }
+        Ok(Parts { attribute, total: sizes.iter().sum(), sizes })
+    }
+
+    // <cpos> are the sorted positions of the item in a corpus of <corpus_size>
+    // tokens
+    fn dispersion(&self, cpos: &[INT], corpus_size: INT) -> CQiResult<Dispersion> {
+        let mut counts = vec![0; self.sizes.len()];
+        for struc in self.attribute.cpos2struc(cpos)? {
+            if let Some(count) = usize::try_from(struc).ok().and_then(|struc| counts.get_mut(struc)) {
+                *count += 1;
+            }
+        }
+
+        let in_parts: INT = counts.iter().sum();
+        if in_parts == 0 {
+            return Ok(Dispersion { frequency: cpos.len() as INT, range: 0, juilland_d: 0.0, gries_dp: 1.0, arf: arf(cpos, corpus_size) });
+        }
+
+        let n = self.sizes.len() as f64;
+        let relative: Vec<f64> = counts.iter().zip(&self.sizes).map(|(&v, &size)| v as f64 / size as f64).collect();
+        let mean = relative.iter().sum::<f64>() / n;
+        let sd = (relative.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / n).sqrt();
+        let juilland_d = if n > 1.0 { 1.0 - sd / mean / (n - 1.0).sqrt() } else { 1.0 };
+
+        let gries_dp = 0.5 * counts.iter().zip(&self.sizes)
+            .map(|(&v, &size)| (v as f64 / in_parts as f64 - size as f64 / self.total as f64).abs())
+            .sum::<f64>();
+
+        Ok(Dispersion {
+            frequency: cpos.len() as INT,
+            range: counts.iter().filter(|&&v| v > 0).count() as INT,
+            juilland_d,
+            gries_dp,
+            arf: arf(cpos, corpus_size),
+        })
+    }
+}
+
+// Distances between consecutive occurrences, wrapping around the end of the
+// corpus, each counted up to the corpus size over the frequency.
+fn arf(cpos: &[INT], corpus_size: INT) -> f64 {
+    let (first, last) = match (cpos.first(), cpos.last()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return 0.0,
+    };
+    let chunk = corpus_size as f64 / cpos.len() as f64;
+
+    let distances = cpos.windows(2).map(|w| w[1] - w[0]).chain(Some(first + corpus_size - last));
+    distances.map(|d| (d as f64).min(chunk)).sum::<f64>() / chunk
+}
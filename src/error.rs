@@ -0,0 +1,115 @@
+use std::fmt;
+use std::io::Error as IoError;
+use num_traits::FromPrimitive;
+use crate::cqi_consts::*;
+use crate::WORD;
+
+#[derive(Debug)]
+pub enum CQiError {
+    // the underlying transport failed
+    Io(IoError),
+    // the server sent a response word that is not defined by CQi
+    UnknownResponse(WORD),
+    // the server sent a valid response that doesn't fit the command
+    UnexpectedResponse { expected: WORD, received: WORD },
+    // the payload of a response could not be decoded
+    InvalidData(String),
+    // a length prefix in a response is above the configured DecodeLimits
+    LimitExceeded { what: &'static str, len: usize, limit: usize },
+    // a command can't be sent with the given arguments or configuration
+    InvalidArgument(String),
+    // errors reported by the server, general errors may carry the message
+    // retrieved via COMMANDS::CTRL_LAST_GENERAL_ERROR
+    Error(ERROR, Option<String>),
+    ClError(CL_ERROR),
+    CqpError(CQP_ERROR, Option<String>),
+}
+
+pub type CQiResult<T> = Result<T, CQiError>;
+
+impl CQiError {
+    // Turns a response word that wasn't the expected one into the matching error.
+    pub(crate) fn from_response(expected: WORD, received: WORD) -> CQiError {
+        let error = match ResponseType::from_u8((received >> 8) as u8) {
+            Some(ResponseType::ERROR) => ERROR::from_u16(received).map(|e| CQiError::Error(e, None)),
+            Some(ResponseType::CL_ERROR) => CL_ERROR::from_u16(received).map(CQiError::ClError),
+            Some(ResponseType::CQP_ERROR) => CQP_ERROR::from_u16(received).map(|e| CQiError::CqpError(e, None)),
+            Some(ResponseType::STATUS) => STATUS::from_u16(received).map(|_| CQiError::UnexpectedResponse { expected, received }),
+            Some(ResponseType::DATA) => DATA::from_u16(received).map(|_| CQiError::UnexpectedResponse { expected, received }),
+            None => None,
+        };
+
+        error.unwrap_or(CQiError::UnknownResponse(received))
+    }
+
+    // Whether the server has a message for this error in CTRL_LAST_GENERAL_ERROR.
+    pub fn is_general(&self) -> bool {
+        matches!(self, CQiError::Error(ERROR::GENERAL_ERROR, _) | CQiError::CqpError(CQP_ERROR::GENERAL, _))
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        match self {
+            CQiError::Error(_, msg) | CQiError::CqpError(_, msg) => msg.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn with_message(self, message: String) -> CQiError {
+        match self {
+            CQiError::Error(e, _) => CQiError::Error(e, Some(message)),
+            CQiError::CqpError(e, _) => CQiError::CqpError(e, Some(message)),
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for CQiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CQiError::Io(e) => write!(f, "I/O error: {}", e),
+            CQiError::UnknownResponse(r) => write!(f, "unknown response 0x{:04X}", r),
+            CQiError::UnexpectedResponse { expected, received } => {
+                write!(f, "expected response {}, received {}", describe_response(*expected), describe_response(*received))
+            },
+            CQiError::InvalidData(msg) => write!(f, "invalid data: {}", msg),
+            CQiError::LimitExceeded { what, len, limit } => write!(f, "{} length {} exceeds limit {}", what, len, limit),
+            CQiError::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            CQiError::Error(e, None) => write!(f, "ERROR::{:?}", e),
+            CQiError::Error(e, Some(msg)) => write!(f, "ERROR::{:?}: {}", e, msg),
+            CQiError::ClError(e) => write!(f, "CL_ERROR::{:?}", e),
+            CQiError::CqpError(e, None) => write!(f, "CQP_ERROR::{:?}", e),
+            CQiError::CqpError(e, Some(msg)) => write!(f, "CQP_ERROR::{:?}: {}", e, msg),
+        }
+    }
+}
+
+impl std::error::Error for CQiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CQiError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<IoError> for CQiError {
+    fn from(e: IoError) -> CQiError {
+        CQiError::Io(e)
+    }
+}
+
+pub(crate) fn describe_response(r: WORD) -> String {
+    let name = match ResponseType::from_u8((r >> 8) as u8) {
+        Some(ResponseType::STATUS) => STATUS::from_u16(r).map(|x| format!("STATUS::{:?}", x)),
+        Some(ResponseType::ERROR) => ERROR::from_u16(r).map(|x| format!("ERROR::{:?}", x)),
+        Some(ResponseType::DATA) => DATA::from_u16(r).map(|x| format!("DATA::{:?}", x)),
+        Some(ResponseType::CL_ERROR) => CL_ERROR::from_u16(r).map(|x| format!("CL_ERROR::{:?}", x)),
+        Some(ResponseType::CQP_ERROR) => CQP_ERROR::from_u16(r).map(|x| format!("CQP_ERROR::{:?}", x)),
+        None => None,
+    };
+
+    match name {
+        Some(name) => name,
+        None => format!("0x{:04X}", r),
+    }
+}
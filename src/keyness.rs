@@ -0,0 +1,259 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Read, Write};
+use crate::measures::{sort_by_score, Contingency, BATCH};
+use crate::*;
+
+// Keywords of a target against a reference, each a corpus or a subcorpus.
+//
+//     let keywords = Keyness::new(&news, &corpus, "lemma")
+//         .min_frequency(5)
+//         .min_log_likelihood(6.63)
+//         .positive_only(true)
+//         .compute()?;
+//
+// Frequencies are counted over every token of a corpus, or every token
+// within the matches of a subcorpus, and compared by string, so target and
+// reference may come from different corpora. A target subcorpus of the
+// reference corpus is part of the reference by default, which pulls the
+// scores towards zero; exclude_target() compares it to the rest of the
+// corpus instead.
+pub struct Keyness<'s, 'a, S: Read + Write> {
+    target: Sample<'s, 'a, S>,
+    reference: Sample<'s, 'a, S>,
+    attribute: String,
+    min_frequency: INT,
+    min_log_likelihood: f64,
+    min_log_ratio: f64,
+    positive_only: bool,
+    exclude_target: bool,
+    sort_by: KeynessMeasure,
+}
+
+// One side of a keyness comparison.
+pub enum Sample<'s, 'a, S: Read + Write> {
+    Corpus(&'s Corpus<'a, S>),
+    Subcorpus(&'s Subcorpus<'a, S>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeynessMeasure {
+    LogLikelihood,
+    ChiSquare,
+    PercentDiff,
+    LogRatio,
+    OddsRatio,
+}
+
+// Frequencies and scores of one string. %DIFF, log ratio and odds ratio are
+// effect sizes, the first two are positive for strings overused in the
+// target and negative for underused ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyword {
+    pub string: STRING,
+    pub target_frequency: INT,
+    pub reference_frequency: INT,
+    pub log_likelihood: f64,
+    pub chi_square: f64,
+    pub percent_diff: f64,
+    pub log_ratio: f64,
+    pub odds_ratio: f64,
+}
+
+impl Keyword {
+
+    pub fn score(&self, measure: KeynessMeasure) -> f64 {
+        match measure {
+            KeynessMeasure::LogLikelihood => self.log_likelihood,
+            KeynessMeasure::ChiSquare => self.chi_square,
+            KeynessMeasure::PercentDiff => self.percent_diff,
+            KeynessMeasure::LogRatio => self.log_ratio,
+            KeynessMeasure::OddsRatio => self.odds_ratio,
+        }
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.log_ratio > 0.0
+    }
+}
+
+impl<'s, 'a, S: Read + Write> Keyness<'s, 'a, S> {
+
+    // compares the strings of the positional attribute <attribute>
+    pub fn new(target: impl Into<Sample<'s, 'a, S>>, reference: impl Into<Sample<'s, 'a, S>>, attribute: &str) -> Keyness<'s, 'a, S> {
+        Keyness {
+            target: target.into(),
+            reference: reference.into(),
+            attribute: attribute.to_string(),
+            min_frequency: 0,
+            min_log_likelihood: 0.0,
+            min_log_ratio: 0.0,
+            positive_only: false,
+            exclude_target: false,
+            sort_by: KeynessMeasure::LogLikelihood,
+        }
+    }
+
+    // leaves out strings seen less often in the target
+    pub fn min_frequency(mut self, min_frequency: INT) -> Keyness<'s, 'a, S> {
+        self.min_frequency = min_frequency;
+        self
+    }
+
+    // e.g. 3.84 for p < 0.05 or 6.63 for p < 0.01
+    pub fn min_log_likelihood(mut self, min_log_likelihood: f64) -> Keyness<'s, 'a, S> {
+        self.min_log_likelihood = min_log_likelihood;
+        self
+    }
+
+    // leaves out strings whose log ratio is closer to 0, in either direction
+    pub fn min_log_ratio(mut self, min_log_ratio: f64) -> Keyness<'s, 'a, S> {
+        self.min_log_ratio = min_log_ratio;
+        self
+    }
+
+    // only strings overused in the target
+    pub fn positive_only(mut self, positive_only: bool) -> Keyness<'s, 'a, S> {
+        self.positive_only = positive_only;
+        self
+    }
+
+    // subtracts the target from the reference if the target is a subcorpus
+    // of the reference corpus, no effect otherwise
+    pub fn exclude_target(mut self, exclude_target: bool) -> Keyness<'s, 'a, S> {
+        self.exclude_target = exclude_target;
+        self
+    }
+
+    // highest score first, log-likelihood by default
+    pub fn sort_by(mut self, measure: KeynessMeasure) -> Keyness<'s, 'a, S> {
+        self.sort_by = measure;
+        self
+    }
+
+    pub fn compute(&self) -> CQiResult<Vec<Keyword>> {
+        let (target, target_size) = self.target.frequencies(&self.attribute)?;
+        let (mut reference, mut reference_size) = self.reference.frequencies(&self.attribute)?;
+        if self.exclude_target && self.target.is_part_of(&self.reference) {
+            for (string, frequency) in &target {
+                if let Some(reference) = reference.get_mut(string) {
+                    *reference -= frequency;
+                }
+            }
+            reference_size -= target_size;
+        }
+
+        let strings: BTreeSet<&STRING> = target.keys().chain(reference.keys()).collect();
+        let mut keywords: Vec<Keyword> = strings.into_iter()
+            .map(|string| {
+                let target_frequency = target.get(string).copied().unwrap_or(0);
+                let reference_frequency = reference.get(string).copied().unwrap_or(0);
+                let table = Contingency::new(
+                    target_frequency as f64,
+                    reference_frequency as f64,
+                    target_size as f64,
+                    reference_size as f64,
+                );
+
+                Keyword {
+                    string: string.clone(),
+                    target_frequency,
+                    reference_frequency,
+                    log_likelihood: table.log_likelihood(),
+                    chi_square: table.chi_square(),
+                    percent_diff: table.percent_diff(),
+                    log_ratio: table.log_ratio(),
+                    odds_ratio: table.odds_ratio(),
+                }
+            })
+            .filter(|k| k.target_frequency >= self.min_frequency
+                && k.log_likelihood >= self.min_log_likelihood
+                && k.log_ratio.abs() >= self.min_log_ratio
+                && (k.is_positive() || !self.positive_only))
+            .collect();
+
+        sort_by_score(&mut keywords, |k| k.score(self.sort_by), |k| &k.string);
+        Ok(keywords)
+    }
+}
+
+impl<'s, 'a, S: Read + Write> Sample<'s, 'a, S> {
+
+    // subcorpora count overlapping matches once, so their tokens are a
+    // subset of the mother corpus
+    fn is_part_of(&self, other: &Sample<'s, 'a, S>) -> bool {
+        match (self, other) {
+            (Sample::Subcorpus(subcorpus), Sample::Corpus(corpus)) => subcorpus.mother() == corpus.name(),
+            _ => false,
+        }
+    }
+
+    // frequency of every string of <attribute> and the number of tokens
+    fn frequencies(&self, attribute: &str) -> CQiResult<(HashMap<STRING, INT>, INT)> {
+        match self {
+            Sample::Corpus(corpus) => {
+                let attribute = corpus.p_attribute(attribute)?;
+                let ids: Vec<INT> = (0..attribute.lexicon_size()?).collect();
+
+                let mut frequencies = HashMap::with_capacity(ids.len());
+                for chunk in ids.chunks(BATCH) {
+                    frequencies.extend(attribute.id2str(chunk)?.into_iter().zip(attribute.id2freq(chunk)?));
+                }
+                Ok((frequencies, corpus.size()?))
+            },
+            Sample::Subcorpus(subcorpus) => {
+                let specifier = format!("{}.{}", subcorpus.mother(), attribute);
+                let attribute = PositionalAttribute::new(subcorpus.connection(), specifier);
+
+                // matches come sorted by start, so overlaps are skipped by
+                // never going back before the end of the previous match
+                let mut counts = HashMap::new();
+                let mut cpos = Vec::new();
+                let mut next = 0;
+                let mut size = 0;
+                for m in subcorpus.matches(BATCH as INT)? {
+                    let m = m?;
+                    cpos.extend(m.start.max(next)..=m.end);
+                    next = next.max(m.end + 1);
+
+                    if cpos.len() >= BATCH {
+                        size += count_ids(&attribute, &mut cpos, &mut counts)?;
+                    }
+                }
+                size += count_ids(&attribute, &mut cpos, &mut counts)?;
+
+                let ids: Vec<INT> = counts.keys().copied().collect();
+                let mut frequencies = HashMap::with_capacity(ids.len());
+                for chunk in ids.chunks(BATCH) {
+                    frequencies.extend(attribute.id2str(chunk)?.into_iter().zip(chunk.iter().map(|id| counts[id])));
+                }
+                Ok((frequencies, size))
+            },
+        }
+    }
+}
+
+// Adds the IDs at <cpos> to <counts> and empties it, returns the number of
+// positions.
+fn count_ids<S: Read + Write>(attribute: &PositionalAttribute<S>, cpos: &mut Vec<INT>, counts: &mut HashMap<INT, INT>) -> CQiResult<INT> {
+    let len = cpos.len() as INT;
+
+    for chunk in cpos.chunks(BATCH) {
+        for id in attribute.cpos2id(chunk)? {
+            *counts.entry(id).or_insert(0) += 1;
+        }
+    }
+    cpos.clear();
+    Ok(len)
+}
+
+impl<'s, 'a, S: Read + Write> From<&'s Corpus<'a, S>> for Sample<'s, 'a, S> {
+    fn from(corpus: &'s Corpus<'a, S>) -> Sample<'s, 'a, S> {
+        Sample::Corpus(corpus)
+    }
+}
+
+impl<'s, 'a, S: Read + Write> From<&'s Subcorpus<'a, S>> for Sample<'s, 'a, S> {
+    fn from(subcorpus: &'s Subcorpus<'a, S>) -> Sample<'s, 'a, S> {
+        Sample::Subcorpus(subcorpus)
+    }
+}
@@ -1,36 +1,91 @@
-use std::net::{TcpStream, SocketAddr, ToSocketAddrs};
-use std::io::Result as IoResult;
-use std::io::{Read, Write};
-use std::io::{Error as IoError, ErrorKind as IoErrorKind};
-use byteorder::{ByteOrder, NetworkEndian, ReadBytesExt};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::io::{BufReader, Read, Write};
 use core::fmt::Debug;
 use cqi_consts::*;
 use num_traits::FromPrimitive;
-use std::time::Duration;
 
 #[allow(non_camel_case_types)]
 #[allow(dead_code)]
 pub mod cqi_consts;
+#[cfg(feature = "tokio")]
+mod async_client;
+mod attribute;
+mod builder;
+mod charset;
+mod collocation;
+mod concordance;
+mod corpus;
+mod decode;
+mod dispersion;
+mod error;
+mod keyness;
+mod matches;
+mod measures;
+mod subcorpus;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(test)]
 mod tests;
 
+#[cfg(feature = "tokio")]
+pub use async_client::AsyncCQiConnection;
+pub use attribute::{AlignmentAttribute, PositionalAttribute, StructuralAttribute};
+pub use builder::CQiConnectionBuilder;
+pub use charset::{Charset, RawString};
+pub use collocation::{AssociationMeasure, Collocate, Collocations};
+pub use concordance::{Concordance, ConcordanceLine, ConcordanceLines, Context};
+pub use corpus::Corpus;
+pub use decode::{CQiDecode, CQiValue, DecodeLimits};
+pub use dispersion::{Dispersion, ValueDistribution};
+pub use error::{CQiError, CQiResult};
+pub use keyness::{Keyness, KeynessMeasure, Keyword, Sample};
+pub use matches::{Match, Matches};
+pub use subcorpus::Subcorpus;
+
 pub type BOOL = bool;
 pub type BYTE = u8;
 pub type WORD = u16;
 pub type INT = i32;
 pub type STRING = String;
+#[allow(non_camel_case_types)]
 pub type BOOL_LIST = Vec<BOOL>;
+#[allow(non_camel_case_types)]
 pub type BYTE_LIST = Vec<BYTE>;
+#[allow(non_camel_case_types)]
 pub type INT_LIST = Vec<INT>;
+#[allow(non_camel_case_types)]
 pub type STRING_LIST = Vec<STRING>;
+#[allow(non_camel_case_types)]
 pub type INT_INT = [INT; 2];
+#[allow(non_camel_case_types)]
 pub type INT_INT_INT_INT = [INT; 4];
+#[allow(non_camel_case_types)]
 pub type INT_TABLE = Vec<Vec<INT>>;
 
 
+// single row of a CQP_FDIST_1 result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FdistItem {
+    pub id: INT,
+    pub frequency: INT,
+}
 
+// single row of a CQP_FDIST_2 result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FdistPair {
+    pub id1: INT,
+    pub id2: INT,
+    pub frequency: INT,
+}
+
+// Sizes are validated before a value writes anything, so an argument that
+// can't be encoded fails with CQiError::InvalidArgument instead of producing
+// a wrapped length prefix.
 pub trait CQiData {
     fn repr(&self) -> String;
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()>;
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()>;
 }
 
 impl Debug for dyn CQiData {
@@ -44,7 +99,7 @@ impl CQiData for BOOL {
         format!("{}", &self)
     }
 
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()> {
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
         Ok(stream.write_all(&[*self as BYTE])?)
     }
 }
@@ -54,7 +109,7 @@ impl CQiData for BYTE {
         format!("0x{:X} [= {}]", &self, &self)
     }
 
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()> {
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
         Ok(stream.write_all(&[*self])?)
     }
 }
@@ -64,7 +119,7 @@ impl CQiData for WORD {
         format!("0x{:X} [= {}]", &self, &self)
     }
 
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()> {
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
         Ok(stream.write_all(&(self.to_be_bytes()))?)
     }
 }
@@ -74,7 +129,7 @@ impl CQiData for INT {
         format!("{}", &self)
     }
 
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()> {
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
         Ok(stream.write_all(&(self.to_be_bytes()))?)
     }
 }
@@ -84,9 +139,8 @@ impl CQiData for STRING {
         format!("\"{}\"", &self)
     }
 
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()> {
-        stream.write_all(&(self.len() as WORD).to_be_bytes())?;
-        Ok(stream.write_all(&(self.as_bytes()))?)
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
+        write_cqi_string(stream, self)
     }
 }
 
@@ -95,9 +149,18 @@ impl CQiData for &str {
         format!("\"{}\"", &self)
     }
 
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()> {
-        stream.write_all(&(self.len() as WORD).to_be_bytes())?;
-        Ok(stream.write_all(&(self.as_bytes()))?)
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
+        write_cqi_string(stream, self)
+    }
+}
+
+impl CQiData for RawString {
+    fn repr(&self) -> String {
+        format!("{:?}", String::from_utf8_lossy(&self.0))
+    }
+
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
+        write_cqi_raw(stream, &self.0)
     }
 }
 
@@ -106,7 +169,7 @@ impl CQiData for BOOL_LIST {
         format!("{:?}", &self)
     }
 
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()> {
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
         write_cqi_list(stream, self)
     }
 }
@@ -116,7 +179,7 @@ impl CQiData for BYTE_LIST {
         format!("{:?}.len({})", &self, &self.len())
     }
 
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()> {
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
         write_cqi_list(stream, self)
     }
 }
@@ -126,7 +189,7 @@ impl CQiData for INT_LIST {
         format!("{:?}.len({})", &self, &self.len())
     }
 
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()> {
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
         write_cqi_list(stream, self)
     }
 }
@@ -136,7 +199,47 @@ impl CQiData for STRING_LIST {
         format!("{:?}.len({})", &self, &self.len())
     }
 
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()> {
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
+        write_cqi_list(stream, self)
+    }
+}
+
+impl CQiData for Vec<RawString> {
+    fn repr(&self) -> String {
+        format!("{:?}.len({})", self.iter().map(|s| s.repr()).collect::<Vec<_>>(), &self.len())
+    }
+
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
+        write_cqi_list(stream, self)
+    }
+}
+
+impl CQiData for &[RawString] {
+    fn repr(&self) -> String {
+        format!("{:?}.len({})", self.iter().map(|s| s.repr()).collect::<Vec<_>>(), &self.len())
+    }
+
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
+        write_cqi_list(stream, self)
+    }
+}
+
+impl CQiData for &[INT] {
+    fn repr(&self) -> String {
+        format!("{:?}.len({})", &self, &self.len())
+    }
+
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
+        write_cqi_list(stream, self)
+    }
+}
+
+impl CQiData for &[&str] {
+    fn repr(&self) -> String {
+        format!("{:?}.len({})", &self, &self.len())
+    }
+
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
         write_cqi_list(stream, self)
     }
 }
@@ -146,8 +249,9 @@ impl CQiData for INT_INT {
         format!("{:?}", &self)
     }
 
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()> {
-        write_cqi_list(stream, self)
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
+        // fixed size, no length prefix
+        write_cqi_multiple(stream, self)
     }
 }
 
@@ -156,8 +260,9 @@ impl CQiData for INT_INT_INT_INT {
         format!("{:?}", &self)
     }
 
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()> {
-        write_cqi_list(stream, self)
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
+        // fixed size, no length prefix
+        write_cqi_multiple(stream, self)
     }
 }
 
@@ -173,7 +278,7 @@ impl CQiData for INT_TABLE {
         format!("{:?}.rows({}).cols({})", &self, rows, cols)
     }
 
-    fn write_cqi_bytes(&self, stream: &mut TcpStream) -> IoResult<()> {
+    fn write_cqi_bytes(&self, stream: &mut dyn Write) -> CQiResult<()> {
         let rows = self.len();
         let mut cols = 0;
 
@@ -181,186 +286,372 @@ impl CQiData for INT_TABLE {
             cols = self[0].len();
         }
 
-        stream.write_all(&(rows as INT).to_be_bytes())?;
-        stream.write_all(&(cols as INT).to_be_bytes())?;
+        if self.iter().any(|row| row.len() != cols) {
+            return Err(CQiError::InvalidArgument("table rows differ in length".to_string()));
+        }
+
+        let rows = list_len(rows)?;
+        let cols = list_len(cols)?;
 
-        for row in 0..rows {
-            write_cqi_multiple(stream, &self[row])?;
+        stream.write_all(&rows.to_be_bytes())?;
+        stream.write_all(&cols.to_be_bytes())?;
+
+        for row in self {
+            write_cqi_multiple(stream, row)?;
         }
 
         Ok(())
     }
 }
 
-fn write_cqi_list<T: CQiData>(stream: &mut TcpStream, list: &[T]) -> IoResult<()> {
-    stream.write_all(&(list.len() as INT).to_be_bytes())?;
+fn write_cqi_string(stream: &mut dyn Write, s: &str) -> CQiResult<()> {
+    write_cqi_raw(stream, s.as_bytes())
+}
+
+fn write_cqi_raw(stream: &mut dyn Write, s: &[u8]) -> CQiResult<()> {
+    if s.len() > WORD::MAX as usize {
+        return Err(CQiError::InvalidArgument(format!("string of {} bytes exceeds {} bytes", s.len(), WORD::MAX)));
+    }
+
+    stream.write_all(&(s.len() as WORD).to_be_bytes())?;
+    Ok(stream.write_all(s)?)
+}
+
+fn list_len(len: usize) -> CQiResult<INT> {
+    if len > INT::MAX as usize {
+        return Err(CQiError::InvalidArgument(format!("list of {} elements exceeds {} elements", len, INT::MAX)));
+    }
+
+    Ok(len as INT)
+}
+
+fn write_cqi_list<T: CQiData>(stream: &mut dyn Write, list: &[T]) -> CQiResult<()> {
+    let len = list_len(list.len())?;
+    stream.write_all(&len.to_be_bytes())?;
     write_cqi_multiple(stream, list)
 }
 
-fn write_cqi_multiple<T: CQiData>(stream: &mut TcpStream, list: &[T]) -> IoResult<()> {
+fn write_cqi_multiple<T: CQiData>(stream: &mut dyn Write, list: &[T]) -> CQiResult<()> {
     for elem in list {
         elem.write_cqi_bytes(stream)?;
     }
     Ok(())
 }
 
+// Commands take &self, so that corpus, attribute and subcorpus handles can
+// share one connection. The cells are only borrowed for the duration of a
+// single write, flush or read, never across a whole command.
+pub struct CQiConnection<S: Read + Write = TcpStream> {
+    stream: RefCell<BufReader<S>>,
+    // the command currently being assembled, sent as a whole by flush()
+    buffer: RefCell<Vec<u8>>,
+    // fetch CTRL_LAST_GENERAL_ERROR after general errors
+    fetch_error_messages: bool,
+    limits: DecodeLimits,
+    // only set for TCP connections with per-command timeouts
+    timeouts: RefCell<Option<builder::Timeouts>>,
+    credentials: Option<(String, String)>,
+    // charsets of the corpora used so far, by corpus name
+    charsets: RefCell<HashMap<String, Charset>>,
+    // last number used by unique_subcorpus_name()
+    subcorpus_counter: Cell<u32>,
+}
+
+impl CQiConnection<TcpStream> {
+
+    pub fn new<A: ToSocketAddrs>(address: A) -> CQiResult<CQiConnection> {
+        CQiConnectionBuilder::new().connect(address)
+    }
 
-pub struct CQiConnection {
-    pub stream: TcpStream,
+    pub fn builder() -> CQiConnectionBuilder {
+        CQiConnectionBuilder::new()
+    }
 }
 
-macro_rules! read_cqi_multiple {
-    ($con:ident, $readfun:ident, $num:expr) => (
-        {
-            let len = $num;
+// Struct methods
+impl<S: Read + Write> CQiConnection<S> {
+
+    // Speaks CQi over an already established transport, e.g. a Unix domain
+    // socket, a TLS stream or an in-memory pipe.
+    pub fn from_stream(stream: S) -> CQiConnection<S> {
+        CQiConnection {
+            stream: RefCell::new(BufReader::new(stream)),
+            buffer: RefCell::new(Vec::new()),
+            fetch_error_messages: false,
+            limits: DecodeLimits::default(),
+            timeouts: RefCell::new(None),
+            credentials: None,
+            charsets: RefCell::new(HashMap::new()),
+            subcorpus_counter: Cell::new(0),
+        }
+    }
+
+    // Handle on <corpus> that caches its metadata, no command is sent yet.
+    pub fn corpus(&self, corpus: &str) -> Corpus<'_, S> {
+        Corpus::new(self, corpus)
+    }
+
+    // A subcorpus name that is neither in use on the server nor handed out by
+    // this connection before.
+    pub fn unique_subcorpus_name(&self, corpus: &str) -> CQiResult<String> {
+        let existing = self.cqp_list_subcorpora(corpus)?;
+
+        loop {
+            let n = self.subcorpus_counter.get() + 1;
+            self.subcorpus_counter.set(n);
 
-            let mut data = Vec::with_capacity(len as usize);
-            
-            for _ in 0..len {
-                let value = $con.$readfun()?;
-                data.push(value);
+            let name = format!("Cqi{}", n);
+            if !existing.contains(&name) {
+                return Ok(name);
             }
-        
-            IoResult::Ok(data)
         }
-    );
-}
+    }
 
-macro_rules! read_cqi_list {
-    ($con:ident, $readfun:ident) => (
-        {
-            let len = $con.read_int()?;
+    pub fn set_decode_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
+    }
 
-            read_cqi_multiple!($con, $readfun, len)
+    // Charset of <corpus> (or of the corpus an attribute or subcorpus name
+    // belongs to), asked from the server once and cached afterwards.
+    pub fn charset(&self, corpus: &str) -> CQiResult<Charset> {
+        let corpus = charset::corpus_of(corpus);
+
+        if let Some(charset) = self.charsets.borrow().get(corpus) {
+            return Ok(*charset);
         }
-    );
-}
 
-// Struct methods
-impl CQiConnection {
+        let name = self.corpus_charset(corpus)?;
+        let charset = match Charset::from_name(&name) {
+            Some(charset) => charset,
+            None => return Err(CQiError::InvalidData(format!("unknown charset \"{}\" of corpus {}", name, corpus))),
+        };
 
-    pub fn new<A: ToSocketAddrs>(address: A) -> IoResult<CQiConnection> {
-        let stream = TcpStream::connect(&address)?;
+        self.charsets.borrow_mut().insert(corpus.to_owned(), charset);
+        Ok(charset)
+    }
 
-        let dur = Duration::from_secs(2);
-        stream.set_read_timeout(Some(dur))?;
-        stream.set_write_timeout(Some(dur))?;
+    // Overrides the charset reported by the server, e.g. for corpora that
+    // were encoded with the wrong one. Attribute and subcorpus specifiers
+    // set the charset of their corpus.
+    pub fn set_charset(&self, corpus: &str, charset: Charset) {
+        self.charsets.borrow_mut().insert(charset::corpus_of(corpus).to_owned(), charset);
+    }
 
-        Ok(CQiConnection { stream: stream })
+    fn encode_strings(&self, corpus: &str, strings: &[&str]) -> CQiResult<Vec<RawString>> {
+        let charset = self.charset(corpus)?;
+        strings.iter().map(|s| Ok(RawString(charset.encode(s)?.into_owned()))).collect()
     }
 
-    pub fn write<A: CQiData>(&mut self, data: A) -> IoResult<()> {
-        data.write_cqi_bytes(&mut self.stream)
+    fn decode_strings(&self, corpus: &str, strings: Vec<RawString>) -> CQiResult<STRING_LIST> {
+        let charset = self.charset(corpus)?;
+        strings.into_iter().map(|s| charset.decode(s.0)).collect()
     }
 
-    pub fn write_boxed(&mut self, data: Box<dyn CQiData>) -> IoResult<()> {
-        (*data).write_cqi_bytes(&mut self.stream)
+    pub fn get_ref(&self) -> Ref<'_, S> {
+        Ref::map(self.stream.borrow(), |stream| stream.get_ref())
     }
 
-    pub fn read_bool(&mut self) -> IoResult<BOOL> {
-        Ok(self.stream.read_u8()? > 0)
+    pub fn get_mut(&mut self) -> &mut S {
+        self.stream.get_mut().get_mut()
     }
 
-    pub fn read_byte(&mut self) -> IoResult<BYTE> {
-        Ok(self.stream.read_u8()?)
+    // When enabled, every ERROR::GENERAL_ERROR and CQP_ERROR::GENERAL response
+    // is followed up with COMMANDS::CTRL_LAST_GENERAL_ERROR and the server's
+    // message is attached to the returned CQiError.
+    pub fn set_fetch_error_messages(&mut self, enabled: bool) {
+        self.fetch_error_messages = enabled;
     }
 
-    pub fn read_word(&mut self) -> IoResult<WORD> {
-        Ok(self.stream.read_u16::<NetworkEndian>()?)
+    // Data is only buffered by write() and write_boxed(), it's sent to the
+    // server by flush() or the next read. If a value can't be encoded, the
+    // whole pending command is discarded so nothing half-written goes out.
+    pub fn write<A: CQiData>(&self, data: A) -> CQiResult<()> {
+        self.write_dyn(&data)
     }
 
-    pub fn read_int(&mut self) -> IoResult<INT> {
-        Ok(self.stream.read_i32::<NetworkEndian>()?)
+    pub fn write_boxed(&self, data: Box<dyn CQiData>) -> CQiResult<()> {
+        self.write_dyn(&*data)
     }
 
-    pub fn read_string(&mut self) -> IoResult<STRING> {
-        let len = self.read_word()?;
+    fn write_dyn(&self, data: &dyn CQiData) -> CQiResult<()> {
+        let mut buffer = self.buffer.borrow_mut();
+        let result = data.write_cqi_bytes(&mut *buffer);
+        if result.is_err() {
+            buffer.clear();
+        }
+        result
+    }
 
-        let data = read_cqi_multiple!(self, read_byte, len)?;
+    pub fn flush(&self) -> CQiResult<()> {
+        let mut buffer = self.buffer.borrow_mut();
+        if !buffer.is_empty() {
+            let mut stream = self.stream.borrow_mut();
+            let stream = stream.get_mut();
+            stream.write_all(&buffer)?;
+            stream.flush()?;
+            buffer.clear();
+        }
+        Ok(())
+    }
 
-        match String::from_utf8(data) {
-            Ok(str) => Ok(str),
-            Err(_) => Err(IoError::new(IoErrorKind::InvalidData, "Received string bytes are not utf8")),
+    fn prepare_command(&self, command: WORD) -> CQiResult<()> {
+        match &mut *self.timeouts.borrow_mut() {
+            Some(timeouts) => timeouts.apply(command),
+            None => Ok(()),
         }
     }
 
-    pub fn read_bool_list(&mut self) -> IoResult<BOOL_LIST> {
-        read_cqi_list!(self, read_bool)
+    fn reader(&self) -> CQiResult<RefMut<'_, BufReader<S>>> {
+        self.flush()?;
+        Ok(self.stream.borrow_mut())
+    }
+
+    pub fn read<T: CQiDecode>(&self) -> CQiResult<T> {
+        T::read_cqi_bytes(&mut *self.reader()?, &self.limits)
+    }
+
+    // reads a value whose type is only known from its DATA header
+    pub fn read_value(&self, datatype: DATA) -> CQiResult<CQiValue> {
+        CQiValue::read_cqi_value(datatype, &mut *self.reader()?, &self.limits)
+    }
+
+    pub fn read_bool(&self) -> CQiResult<BOOL> {
+        self.read()
+    }
+
+    pub fn read_byte(&self) -> CQiResult<BYTE> {
+        self.read()
+    }
+
+    pub fn read_word(&self) -> CQiResult<WORD> {
+        self.read()
+    }
+
+    pub fn read_int(&self) -> CQiResult<INT> {
+        self.read()
+    }
+
+    pub fn read_string(&self) -> CQiResult<STRING> {
+        self.read()
+    }
+
+    pub fn read_raw_string(&self) -> CQiResult<RawString> {
+        self.read()
+    }
+
+    pub fn read_raw_string_list(&self) -> CQiResult<Vec<RawString>> {
+        self.read()
+    }
+
+    pub fn read_bool_list(&self) -> CQiResult<BOOL_LIST> {
+        self.read()
+    }
+
+    pub fn read_byte_list(&self) -> CQiResult<BYTE_LIST> {
+        self.read()
+    }
+
+    pub fn read_int_list(&self) -> CQiResult<INT_LIST> {
+        self.read()
     }
 
-    pub fn read_byte_list(&mut self) -> IoResult<BYTE_LIST> {
-        read_cqi_list!(self, read_byte)
+    pub fn read_string_list(&self) -> CQiResult<STRING_LIST> {
+        self.read()
     }
 
-    pub fn read_int_list(&mut self) -> IoResult<INT_LIST> {
-        read_cqi_list!(self, read_int)
+    pub fn read_int_int(&self) -> CQiResult<INT_INT> {
+        self.read()
     }
 
-    pub fn read_string_list(&mut self) -> IoResult<STRING_LIST> {
-        read_cqi_list!(self, read_string)
+    pub fn read_int_int_int_int(&self) -> CQiResult<INT_INT_INT_INT> {
+        self.read()
     }
 
-    pub fn read_int_int(&mut self) -> IoResult<INT_INT> {
-        Ok(
-            [
-                self.read_int()?,
-                self.read_int()?,
-            ]
-        )
+    pub fn read_int_table(&self) -> CQiResult<INT_TABLE> {
+        self.read()
     }
 
-    pub fn read_int_int_int_int(&mut self) -> IoResult<INT_INT_INT_INT> {
-        Ok(
-            [
-                self.read_int()?,
-                self.read_int()?,
-                self.read_int()?,
-                self.read_int()?,
-            ]
-        )
+    fn read_fdist_table(&self, cols: usize) -> CQiResult<INT_TABLE> {
+        let table = self.read_int_table()?;
+
+        if table.iter().any(|row| row.len() != cols) {
+            return Err(CQiError::InvalidData(format!("expected frequency table with {} columns", cols)));
+        }
+
+        Ok(table)
     }
 
-    pub fn read_int_table(&mut self) -> IoResult<INT_TABLE> {
-        let rows = self.read_int()?;
-        let cols = self.read_int()?;
+    fn expect_response(&self, expected: WORD) -> CQiResult<()> {
+        let r = self.read_word()?;
+
+        if r == expected {
+            return Ok(());
+        }
 
-        let mut data: INT_TABLE = Vec::with_capacity(rows as usize);
+        let error = CQiError::from_response(expected, r);
 
-        for _ in 0..rows {
-            data.push(read_cqi_multiple!(self, read_int, cols)?);
+        // Data of the wrong type still has to be read, or the next command
+        // gets it as its response. The stream is broken anyway if that fails,
+        // so the original error is the more useful one.
+        if let Some(datatype) = DATA::from_u16(r) {
+            if let Ok(mut reader) = self.reader() {
+                let _ = decode::skip_value(datatype, &mut *reader, &self.limits);
+            }
         }
-        
-        Ok(data)
+
+        if self.fetch_error_messages && error.is_general() {
+            // A failure here must not hide the original error. This is
+            // ctrl_last_general_error() without the recursion: a server that
+            // answers it with another error would have it fetch messages
+            // forever.
+            self.write(COMMANDS::CTRL_LAST_GENERAL_ERROR as WORD)?;
+            if let Ok(r) = self.read_word() {
+                if r == DATA::STRING as WORD {
+                    if let Ok(message) = self.read_string() {
+                        return Err(error.with_message(message));
+                    }
+                }
+            }
+        }
+
+        Err(error)
     }
 }
 
 macro_rules! send_cqi_data {
     ( $con:ident, $command:path$(, $( $x:expr ),*)? ) => (
         {
+            $con.prepare_command($command as WORD)?;
             $con.write($command as WORD)?;
             $(
                 $(
                     $con.write($x)?;
                 )*
             )?
-            IoResult::Ok(())
+            $con.flush()
         }
     );
 }
 
-macro_rules! receive_cqi_response {
-    ( $con:ident, $msg_type:ident ) => (
+macro_rules! receive_cqi_data {
+    ( $con:ident, $data_type:path, $readfun:ident ) => (
         {
-            let r = $con.read_word()?;
-            IoResult::Ok($msg_type::from_u16(r).expect("Malformed response."))
+            $con.expect_response($data_type as WORD)?;
+            $con.$readfun()
         }
     );
 }
 
+macro_rules! receive_cqi_status {
+    ( $con:ident, $status:path ) => (
+        $con.expect_response($status as WORD)
+    );
+}
+
 // CQi commands
-impl CQiConnection {
+impl<S: Read + Write> CQiConnection<S> {
 
-    pub fn ctr_connect(&mut self, user: &str, password: &str) -> IoResult<STATUS> {
+    pub fn ctr_connect(&self, user: &str, password: &str) -> CQiResult<()> {
         // self.write(COMMANDS::CTRL_CONNECT as WORD)?;
         // self.write(user)?;
         // self.write(password)?;
@@ -369,13 +660,395 @@ impl CQiConnection {
             user,
             password
         )?;
-        receive_cqi_response!(self, STATUS)
+        receive_cqi_status!(self, STATUS::CONNECT_OK)
+    }
+
+    // CTRL_CONNECT with the credentials given to CQiConnectionBuilder
+    pub fn login(&self) -> CQiResult<()> {
+        match &self.credentials {
+            Some((user, password)) => self.ctr_connect(user, password),
+            None => Err(CQiError::InvalidArgument("no credentials configured".to_string())),
+        }
     }
 
-    pub fn ctrl_ping(&mut self) -> IoResult<STATUS> {
+    pub fn ctrl_ping(&self) -> CQiResult<()> {
         send_cqi_data!(self,
             COMMANDS::CTRL_PING
         )?;
-        receive_cqi_response!(self, STATUS)
+        receive_cqi_status!(self, STATUS::PING_OK)
+    }
+
+    pub fn ctrl_last_general_error(&self) -> CQiResult<STRING> {
+        send_cqi_data!(self,
+            COMMANDS::CTRL_LAST_GENERAL_ERROR
+        )?;
+        receive_cqi_data!(self, DATA::STRING, read_string)
+    }
+
+    pub fn corpus_list_corpora(&self) -> CQiResult<STRING_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_LIST_CORPORA
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_string_list)
+    }
+
+    pub fn corpus_charset(&self, corpus: &str) -> CQiResult<STRING> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_CHARSET,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING, read_string)
+    }
+
+    pub fn corpus_properties(&self, corpus: &str) -> CQiResult<STRING_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_PROPERTIES,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_string_list)
+    }
+
+    pub fn corpus_positional_attributes(&self, corpus: &str) -> CQiResult<STRING_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_POSITIONAL_ATTRIBUTES,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_string_list)
+    }
+
+    pub fn corpus_structural_attributes(&self, corpus: &str) -> CQiResult<STRING_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_STRUCTURAL_ATTRIBUTES,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_string_list)
+    }
+
+    pub fn corpus_structural_attribute_has_values(&self, attribute: &str) -> CQiResult<BOOL> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_STRUCTURAL_ATTRIBUTE_HAS_VALUES,
+            attribute
+        )?;
+        receive_cqi_data!(self, DATA::BOOL, read_bool)
+    }
+
+    pub fn corpus_alignment_attributes(&self, corpus: &str) -> CQiResult<STRING_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_ALIGNMENT_ATTRIBUTES,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_string_list)
+    }
+
+    // decoded with the corpus charset like the CL_* strings
+    pub fn corpus_full_name(&self, corpus: &str) -> CQiResult<STRING> {
+        let name = self.corpus_full_name_raw(corpus)?;
+        self.charset(corpus)?.decode(name.0)
+    }
+
+    pub fn corpus_full_name_raw(&self, corpus: &str) -> CQiResult<RawString> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_FULL_NAME,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING, read_raw_string)
+    }
+
+    pub fn corpus_info(&self, corpus: &str) -> CQiResult<STRING_LIST> {
+        let info = self.corpus_info_raw(corpus)?;
+        self.decode_strings(corpus, info)
+    }
+
+    pub fn corpus_info_raw(&self, corpus: &str) -> CQiResult<Vec<RawString>> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_INFO,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_raw_string_list)
+    }
+
+    pub fn corpus_drop_corpus(&self, corpus: &str) -> CQiResult<()> {
+        send_cqi_data!(self,
+            COMMANDS::CORPUS_DROP_CORPUS,
+            corpus
+        )?;
+        receive_cqi_status!(self, STATUS::OK)
+    }
+
+    pub fn cl_attribute_size(&self, attribute: &str) -> CQiResult<INT> {
+        send_cqi_data!(self,
+            COMMANDS::CL_ATTRIBUTE_SIZE,
+            attribute
+        )?;
+        receive_cqi_data!(self, DATA::INT, read_int)
+    }
+
+    pub fn cl_lexicon_size(&self, attribute: &str) -> CQiResult<INT> {
+        send_cqi_data!(self,
+            COMMANDS::CL_LEXICON_SIZE,
+            attribute
+        )?;
+        receive_cqi_data!(self, DATA::INT, read_int)
+    }
+
+    pub fn cl_drop_attribute(&self, attribute: &str) -> CQiResult<()> {
+        send_cqi_data!(self,
+            COMMANDS::CL_DROP_ATTRIBUTE,
+            attribute
+        )?;
+        receive_cqi_status!(self, STATUS::OK)
+    }
+
+    // Strings of the CL_* and CQP_QUERY commands are converted from and to
+    // the charset of the corpus, the _raw variants pass them on unchanged.
+    pub fn cl_str2id(&self, attribute: &str, strings: &[&str]) -> CQiResult<INT_LIST> {
+        let strings = self.encode_strings(attribute, strings)?;
+        self.cl_str2id_raw(attribute, &strings)
+    }
+
+    pub fn cl_str2id_raw(&self, attribute: &str, strings: &[RawString]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_STR2ID,
+            attribute,
+            strings
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub fn cl_id2str(&self, attribute: &str, ids: &[INT]) -> CQiResult<STRING_LIST> {
+        let strings = self.cl_id2str_raw(attribute, ids)?;
+        self.decode_strings(attribute, strings)
+    }
+
+    pub fn cl_id2str_raw(&self, attribute: &str, ids: &[INT]) -> CQiResult<Vec<RawString>> {
+        send_cqi_data!(self,
+            COMMANDS::CL_ID2STR,
+            attribute,
+            ids
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_raw_string_list)
+    }
+
+    pub fn cl_id2freq(&self, attribute: &str, ids: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_ID2FREQ,
+            attribute,
+            ids
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub fn cl_cpos2id(&self, attribute: &str, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_CPOS2ID,
+            attribute,
+            cpos
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub fn cl_cpos2str(&self, attribute: &str, cpos: &[INT]) -> CQiResult<STRING_LIST> {
+        let strings = self.cl_cpos2str_raw(attribute, cpos)?;
+        self.decode_strings(attribute, strings)
+    }
+
+    pub fn cl_cpos2str_raw(&self, attribute: &str, cpos: &[INT]) -> CQiResult<Vec<RawString>> {
+        send_cqi_data!(self,
+            COMMANDS::CL_CPOS2STR,
+            attribute,
+            cpos
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_raw_string_list)
+    }
+
+    pub fn cl_cpos2struc(&self, attribute: &str, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_CPOS2STRUC,
+            attribute,
+            cpos
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub fn cl_cpos2lbound(&self, attribute: &str, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_CPOS2LBOUND,
+            attribute,
+            cpos
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub fn cl_cpos2rbound(&self, attribute: &str, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_CPOS2RBOUND,
+            attribute,
+            cpos
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub fn cl_cpos2alg(&self, attribute: &str, cpos: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_CPOS2ALG,
+            attribute,
+            cpos
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub fn cl_struc2str(&self, attribute: &str, strucs: &[INT]) -> CQiResult<STRING_LIST> {
+        let strings = self.cl_struc2str_raw(attribute, strucs)?;
+        self.decode_strings(attribute, strings)
+    }
+
+    pub fn cl_struc2str_raw(&self, attribute: &str, strucs: &[INT]) -> CQiResult<Vec<RawString>> {
+        send_cqi_data!(self,
+            COMMANDS::CL_STRUC2STR,
+            attribute,
+            strucs
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_raw_string_list)
+    }
+
+    pub fn cl_id2cpos(&self, attribute: &str, id: INT) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_ID2CPOS,
+            attribute,
+            id
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub fn cl_idlist2cpos(&self, attribute: &str, id_list: &[INT]) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CL_IDLIST2CPOS,
+            attribute,
+            id_list
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub fn cl_regex2id(&self, attribute: &str, regex: &str) -> CQiResult<INT_LIST> {
+        let regex = self.charset(attribute)?.encode(regex)?.into_owned();
+        send_cqi_data!(self,
+            COMMANDS::CL_REGEX2ID,
+            attribute,
+            RawString(regex)
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub fn cl_struc2cpos(&self, attribute: &str, struc: INT) -> CQiResult<INT_INT> {
+        send_cqi_data!(self,
+            COMMANDS::CL_STRUC2CPOS,
+            attribute,
+            struc
+        )?;
+        receive_cqi_data!(self, DATA::INT_INT, read_int_int)
+    }
+
+    pub fn cl_alg2cpos(&self, attribute: &str, alg: INT) -> CQiResult<INT_INT_INT_INT> {
+        send_cqi_data!(self,
+            COMMANDS::CL_ALG2CPOS,
+            attribute,
+            alg
+        )?;
+        receive_cqi_data!(self, DATA::INT_INT_INT_INT, read_int_int_int_int)
+    }
+
+    // The subcorpus name is checked before sending, and a missing ; is added
+    // to the query. The _raw variant sends the query as it is.
+    pub fn cqp_query(&self, mother_corpus: &str, subcorpus_name: &str, query: &str) -> CQiResult<()> {
+        let query = subcorpus::terminate_query(query);
+        let query = self.charset(mother_corpus)?.encode(&query)?.into_owned();
+        self.cqp_query_raw(mother_corpus, subcorpus_name, &query)
+    }
+
+    pub fn cqp_query_raw(&self, mother_corpus: &str, subcorpus_name: &str, query: &[u8]) -> CQiResult<()> {
+        subcorpus::check_name(subcorpus_name)?;
+        send_cqi_data!(self,
+            COMMANDS::CQP_QUERY,
+            mother_corpus,
+            subcorpus_name,
+            RawString::from(query)
+        )?;
+        receive_cqi_status!(self, STATUS::OK)
+    }
+
+    pub fn cqp_list_subcorpora(&self, corpus: &str) -> CQiResult<STRING_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_LIST_SUBCORPORA,
+            corpus
+        )?;
+        receive_cqi_data!(self, DATA::STRING_LIST, read_string_list)
+    }
+
+    pub fn cqp_subcorpus_size(&self, subcorpus: &str) -> CQiResult<INT> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_SUBCORPUS_SIZE,
+            subcorpus
+        )?;
+        receive_cqi_data!(self, DATA::INT, read_int)
+    }
+
+    pub fn cqp_subcorpus_has_field(&self, subcorpus: &str, field: FIELD) -> CQiResult<BOOL> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_SUBCORPUS_HAS_FIELD,
+            subcorpus,
+            field as BYTE
+        )?;
+        receive_cqi_data!(self, DATA::BOOL, read_bool)
+    }
+
+    pub fn cqp_dump_subcorpus(&self, subcorpus: &str, field: FIELD, first: INT, last: INT) -> CQiResult<INT_LIST> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_DUMP_SUBCORPUS,
+            subcorpus,
+            field as BYTE,
+            first,
+            last
+        )?;
+        receive_cqi_data!(self, DATA::INT_LIST, read_int_list)
+    }
+
+    pub fn cqp_drop_subcorpus(&self, subcorpus: &str) -> CQiResult<()> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_DROP_SUBCORPUS,
+            subcorpus
+        )?;
+        receive_cqi_status!(self, STATUS::OK)
+    }
+
+    pub fn cqp_fdist_1(&self, subcorpus: &str, cutoff: INT, field: FIELD, attribute: &str) -> CQiResult<Vec<FdistItem>> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_FDIST_1,
+            subcorpus,
+            cutoff,
+            field as BYTE,
+            attribute
+        )?;
+        self.expect_response(DATA::INT_TABLE as WORD)?;
+
+        let table = self.read_fdist_table(2)?;
+
+        Ok(table.iter().map(|row| FdistItem { id: row[0], frequency: row[1] }).collect())
+    }
+
+    pub fn cqp_fdist_2(&self, subcorpus: &str, cutoff: INT, field1: FIELD, attribute1: &str, field2: FIELD, attribute2: &str) -> CQiResult<Vec<FdistPair>> {
+        send_cqi_data!(self,
+            COMMANDS::CQP_FDIST_2,
+            subcorpus,
+            cutoff,
+            field1 as BYTE,
+            attribute1,
+            field2 as BYTE,
+            attribute2
+        )?;
+        self.expect_response(DATA::INT_TABLE as WORD)?;
+
+        let table = self.read_fdist_table(3)?;
+
+        Ok(table.iter().map(|row| FdistPair { id1: row[0], id2: row[1], frequency: row[2] }).collect())
     }
 }
\ No newline at end of file
@@ -0,0 +1,120 @@
+use std::io::{Read, Write};
+use std::vec;
+use crate::decode::check_reply_len;
+use crate::*;
+
+// One match of a subcorpus. Target and keyword are None if the subcorpus
+// has no such field or the match has no target/keyword (-1 on the wire).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub start: INT,
+    pub end: INT,
+    pub target: Option<INT>,
+    pub keyword: Option<INT>,
+}
+
+// Walks the matches of a subcorpus one CQP_DUMP_SUBCORPUS page at a time, see
+// Subcorpus::matches(). Only the current page is kept in memory. The
+// iteration ends after the first error.
+pub struct Matches<'s, 'a, S: Read + Write> {
+    subcorpus: &'s Subcorpus<'a, S>,
+    pages: Pages,
+    has_target: bool,
+    has_keyword: bool,
+    page: vec::IntoIter<Match>,
+}
+
+impl<'s, 'a, S: Read + Write> Matches<'s, 'a, S> {
+
+    pub(crate) fn new(subcorpus: &'s Subcorpus<'a, S>, page_size: INT) -> CQiResult<Matches<'s, 'a, S>> {
+        Ok(Matches {
+            subcorpus,
+            pages: Pages::new(subcorpus.size()?, page_size)?,
+            has_target: subcorpus.has_field(FIELD::TARGET)?,
+            has_keyword: subcorpus.has_field(FIELD::KEYWORD)?,
+            page: Vec::new().into_iter(),
+        })
+    }
+
+    fn fetch(&self, first: INT, last: INT) -> CQiResult<Vec<Match>> {
+        // the fields are lined up by index, so each needs a value per match
+        let dump = |field: FIELD| -> CQiResult<INT_LIST> {
+            let values = self.subcorpus.dump(field, first, last)?;
+            check_reply_len(&values, (last - first + 1) as usize, &format!("{:?} of {}", field, self.subcorpus.specifier()))?;
+            Ok(values)
+        };
+        let optional = |has: bool, field: FIELD| if has { dump(field).map(Some) } else { Ok(None) };
+        let starts = dump(FIELD::MATCH)?;
+        let ends = dump(FIELD::MATCHEND)?;
+        let targets = optional(self.has_target, FIELD::TARGET)?;
+        let keywords = optional(self.has_keyword, FIELD::KEYWORD)?;
+        let at = |field: &Option<INT_LIST>, i: usize| field.as_ref().map(|f| f[i]).filter(|&cpos| cpos >= 0);
+
+        Ok((0..starts.len()).map(|i| Match {
+            start: starts[i],
+            end: ends[i],
+            target: at(&targets, i),
+            keyword: at(&keywords, i),
+        }).collect())
+    }
+}
+
+impl<S: Read + Write> Iterator for Matches<'_, '_, S> {
+    type Item = CQiResult<Match>;
+
+    fn next(&mut self) -> Option<CQiResult<Match>> {
+        if let Some(m) = self.page.next() {
+            return Some(Ok(m));
+        }
+
+        let (first, last) = self.pages.next()?;
+        match self.fetch(first, last) {
+            Ok(page) => {
+                self.page = page.into_iter();
+                self.page.next().map(Ok)
+            },
+            Err(e) => {
+                self.pages.stop();
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+// First and last match number of each page of a subcorpus with <size>
+// matches.
+#[derive(Debug, Clone)]
+pub(crate) struct Pages {
+    size: INT,
+    next: INT,
+    page_size: INT,
+}
+
+impl Pages {
+
+    pub(crate) fn new(size: INT, page_size: INT) -> CQiResult<Pages> {
+        if page_size <= 0 {
+            return Err(CQiError::InvalidArgument(format!("page size must be positive, got {}", page_size)));
+        }
+        Ok(Pages { size, next: 0, page_size })
+    }
+
+    pub(crate) fn stop(&mut self) {
+        self.next = self.size;
+    }
+}
+
+impl Iterator for Pages {
+    type Item = (INT, INT);
+
+    fn next(&mut self) -> Option<(INT, INT)> {
+        if self.next >= self.size {
+            return None;
+        }
+
+        let first = self.next;
+        let last = first.saturating_add(self.page_size - 1).min(self.size - 1);
+        self.next = last + 1;
+        Some((first, last))
+    }
+}
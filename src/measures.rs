@@ -0,0 +1,119 @@
+// Association measures on a 2x2 contingency table of a word in a sample
+// (a collocation window, a corpus to find keywords in) against a reference
+// (the rest of the corpus, a reference corpus):
+//
+//                  word    other words
+//     sample       o11     o12           r1
+//     reference    o21     o22           r2
+//                  c1      c2            n
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Contingency {
+    o11: f64,
+    o12: f64,
+    o21: f64,
+    o22: f64,
+}
+
+// stands in for zero frequencies in ratios, as in Hardie's log ratio
+const ZERO: f64 = 0.5;
+
+impl Contingency {
+
+    // <in_sample> and <in_reference> occurrences of the word in a sample of
+    // <sample_size> and a reference of <reference_size> tokens
+    pub(crate) fn new(in_sample: f64, in_reference: f64, sample_size: f64, reference_size: f64) -> Contingency {
+        Contingency {
+            o11: in_sample,
+            o12: (sample_size - in_sample).max(0.0),
+            o21: in_reference,
+            o22: (reference_size - in_reference).max(0.0),
+        }
+    }
+
+    fn r1(&self) -> f64 {
+        self.o11 + self.o12
+    }
+
+    fn r2(&self) -> f64 {
+        self.o21 + self.o22
+    }
+
+    fn c1(&self) -> f64 {
+        self.o11 + self.o21
+    }
+
+    fn c2(&self) -> f64 {
+        self.o12 + self.o22
+    }
+
+    fn n(&self) -> f64 {
+        self.r1() + self.r2()
+    }
+
+    pub(crate) fn expected(&self) -> f64 {
+        self.r1() * self.c1() / self.n()
+    }
+
+    // Dunning's G², always positive
+    pub(crate) fn log_likelihood(&self) -> f64 {
+        let n = self.n();
+        let term = |o: f64, e: f64| if o > 0.0 && e > 0.0 { o * (o / e).ln() } else { 0.0 };
+
+        2.0 * (term(self.o11, self.r1() * self.c1() / n)
+            + term(self.o12, self.r1() * self.c2() / n)
+            + term(self.o21, self.r2() * self.c1() / n)
+            + term(self.o22, self.r2() * self.c2() / n))
+    }
+
+    // Pearson's X² without continuity correction
+    pub(crate) fn chi_square(&self) -> f64 {
+        let denominator = self.r1() * self.r2() * self.c1() * self.c2();
+        if denominator == 0.0 {
+            return 0.0;
+        }
+        self.n() * (self.o11 * self.o22 - self.o12 * self.o21).powi(2) / denominator
+    }
+
+    // pointwise mutual information, log2(O / E)
+    pub(crate) fn mutual_information(&self) -> f64 {
+        (self.o11 / self.expected()).log2()
+    }
+
+    pub(crate) fn t_score(&self) -> f64 {
+        if self.o11 == 0.0 {
+            return 0.0;
+        }
+        (self.o11 - self.expected()) / self.o11.sqrt()
+    }
+
+    pub(crate) fn dice(&self) -> f64 {
+        2.0 * self.o11 / (self.r1() + self.c1())
+    }
+
+    // binary log of the ratio of the relative frequencies in sample and
+    // reference
+    pub(crate) fn log_ratio(&self) -> f64 {
+        let relative = |o: f64, size: f64| o.max(ZERO) / size;
+        (relative(self.o11, self.r1()) / relative(self.o21, self.r2())).log2()
+    }
+
+    pub(crate) fn odds_ratio(&self) -> f64 {
+        (self.o11.max(ZERO) * self.o22.max(ZERO)) / (self.o12.max(ZERO) * self.o21.max(ZERO))
+    }
+
+    // Gabrielatos & Marchi's %DIFF of the relative frequencies, with 1e-18
+    // in place of a zero reference frequency
+    pub(crate) fn percent_diff(&self) -> f64 {
+        let sample = self.o11 / self.r1();
+        let reference = (self.o21 / self.r2()).max(1e-18);
+        (sample - reference) * 100.0 / reference
+    }
+}
+
+// positions and IDs per CL_* request
+pub(crate) const BATCH: usize = 1 << 16;
+
+// Highest <score> first, alphabetical by <string> among equal scores.
+pub(crate) fn sort_by_score<T>(items: &mut [T], score: impl Fn(&T) -> f64, string: impl Fn(&T) -> &str) {
+    items.sort_by(|a, b| score(b).total_cmp(&score(a)).then_with(|| string(a).cmp(string(b))));
+}
@@ -0,0 +1,187 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use crate::*;
+
+// Result of a CQP query, see Corpus::query(). Subcorpora created by a query
+// are dropped on the server when the handle goes away, unless persist() was
+// called; handles on existing subcorpora from Corpus::subcorpus() never drop.
+pub struct Subcorpus<'a, S: Read + Write = TcpStream> {
+    connection: &'a CQiConnection<S>,
+    mother: String,
+    name: String,
+    // "MOTHER:Name", the form every CQP_* command expects
+    specifier: String,
+    drop_on_release: bool,
+}
+
+impl<'a, S: Read + Write> Subcorpus<'a, S> {
+
+    pub(crate) fn new(connection: &'a CQiConnection<S>, mother: &str, name: &str, drop_on_release: bool) -> Subcorpus<'a, S> {
+        Subcorpus {
+            connection,
+            mother: mother.to_owned(),
+            name: name.to_owned(),
+            specifier: format!("{}:{}", mother, name),
+            drop_on_release,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mother(&self) -> &str {
+        &self.mother
+    }
+
+    pub fn specifier(&self) -> &str {
+        &self.specifier
+    }
+
+    pub fn connection(&self) -> &'a CQiConnection<S> {
+        self.connection
+    }
+
+    // Keeps the subcorpus on the server after the handle is gone.
+    pub fn persist(mut self) -> Subcorpus<'a, S> {
+        self.drop_on_release = false;
+        self
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        !self.drop_on_release
+    }
+
+    // Drops the subcorpus right away and reports errors, which releasing the
+    // handle can't.
+    pub fn drop_subcorpus(mut self) -> CQiResult<()> {
+        self.drop_on_release = false;
+        self.connection.cqp_drop_subcorpus(&self.specifier)
+    }
+
+    // number of matches
+    pub fn size(&self) -> CQiResult<INT> {
+        self.connection.cqp_subcorpus_size(&self.specifier)
+    }
+
+    pub fn has_field(&self, field: FIELD) -> CQiResult<BOOL> {
+        self.connection.cqp_subcorpus_has_field(&self.specifier, field)
+    }
+
+    // <field> of the matches <first> to <last>, both inclusive
+    pub fn dump(&self, field: FIELD, first: INT, last: INT) -> CQiResult<INT_LIST> {
+        self.connection.cqp_dump_subcorpus(&self.specifier, field, first, last)
+    }
+
+    // Iterates over the matches, dumping <page_size> of them at a time.
+    pub fn matches(&self, page_size: INT) -> CQiResult<Matches<'_, 'a, S>> {
+        Matches::new(self, page_size)
+    }
+
+    // keyword-in-context lines of the matches, see Concordance
+    pub fn concordance(&self) -> Concordance<'_, 'a, S> {
+        Concordance::new(self)
+    }
+
+    // collocates of the matches on <attribute>, see Collocations
+    pub fn collocations(&self, attribute: &PositionalAttribute<'a, S>) -> Collocations<'_, 'a, S> {
+        Collocations::new(self, attribute)
+    }
+
+    // Hits per value of <attribute>, absolute and per million tokens of the
+    // regions with that value. Costs attribute.regions() the first time the
+    // handle is used, then a CL_STRUC2STR and a dump and CL_CPOS2STRUC per
+    // page of matches.
+    pub fn distribution(&self, attribute: &StructuralAttribute<S>) -> CQiResult<Vec<ValueDistribution>> {
+        dispersion::distribution(self, attribute)
+    }
+
+    pub fn fdist_1(&self, cutoff: INT, field: FIELD, attribute: &PositionalAttribute<S>) -> CQiResult<Vec<FdistItem>> {
+        self.connection.cqp_fdist_1(&self.specifier, cutoff, field, attribute.specifier())
+    }
+
+    pub fn fdist_2(
+        &self,
+        cutoff: INT,
+        field1: FIELD,
+        attribute1: &PositionalAttribute<S>,
+        field2: FIELD,
+        attribute2: &PositionalAttribute<S>,
+    ) -> CQiResult<Vec<FdistPair>> {
+        self.connection.cqp_fdist_2(&self.specifier, cutoff, field1, attribute1.specifier(), field2, attribute2.specifier())
+    }
+
+    // fdist_1() with the IDs resolved to strings, most frequent first and
+    // alphabetical among equal counts
+    pub fn frequencies(&self, cutoff: INT, field: FIELD, attribute: &PositionalAttribute<S>) -> CQiResult<Vec<(STRING, INT)>> {
+        let fdist = self.fdist_1(cutoff, field, attribute)?;
+        let strings = attribute.id2str(&fdist.iter().map(|f| f.id).collect::<Vec<_>>())?;
+
+        let mut rows: Vec<_> = strings.into_iter().zip(fdist.iter().map(|f| f.frequency)).collect();
+        sort_frequencies(&mut rows);
+        Ok(rows)
+    }
+
+    // fdist_2() with the IDs resolved to strings, sorted like frequencies()
+    pub fn frequencies_2(
+        &self,
+        cutoff: INT,
+        field1: FIELD,
+        attribute1: &PositionalAttribute<S>,
+        field2: FIELD,
+        attribute2: &PositionalAttribute<S>,
+    ) -> CQiResult<Vec<((STRING, STRING), INT)>> {
+        let fdist = self.fdist_2(cutoff, field1, attribute1, field2, attribute2)?;
+        let strings1 = attribute1.id2str(&fdist.iter().map(|f| f.id1).collect::<Vec<_>>())?;
+        let strings2 = attribute2.id2str(&fdist.iter().map(|f| f.id2).collect::<Vec<_>>())?;
+
+        let mut rows: Vec<_> = strings1.into_iter().zip(strings2).zip(fdist.iter().map(|f| f.frequency)).collect();
+        sort_frequencies(&mut rows);
+        Ok(rows)
+    }
+}
+
+impl<S: Read + Write> Drop for Subcorpus<'_, S> {
+    fn drop(&mut self) {
+        // errors can't be reported from here, and talking to the server
+        // while unwinding could block on a broken connection
+        if self.drop_on_release && !std::thread::panicking() {
+            let _ = self.connection.cqp_drop_subcorpus(&self.specifier);
+        }
+    }
+}
+
+impl<S: Read + Write> fmt::Debug for Subcorpus<'_, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Subcorpus({})", self.specifier)
+    }
+}
+
+fn sort_frequencies<T: Ord>(rows: &mut [(T, INT)]) {
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+}
+
+// CQP only accepts subcorpus names made of an uppercase letter followed by
+// letters, digits, underscores and hyphens.
+pub(crate) fn check_name(name: &str) -> CQiResult<()> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_uppercase())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(CQiError::InvalidArgument(format!("\"{}\" is not a valid subcorpus name", name)))
+    }
+}
+
+// CQP refuses queries without the terminating semicolon.
+pub(crate) fn terminate_query(query: &str) -> Cow<'_, str> {
+    if query.trim_end().ends_with(';') {
+        Cow::Borrowed(query)
+    } else {
+        Cow::Owned(format!("{};", query.trim_end()))
+    }
+}
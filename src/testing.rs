@@ -0,0 +1,934 @@
+// In-process stand-in for a CQPserver, so client code can be tested offline.
+//
+//     let server = MockServer::new()
+//         .corpus(MockCorpus::fixture())
+//         .on(COMMANDS::CORPUS_INFO, |_| Response::string_list(vec!["scripted".to_string()]))
+//         .spawn()?;
+//     let mut connection = server.connect()?;
+//
+// Commands without a handler are answered from the registered corpora. CQP
+// queries are limited to sequences of token patterns: "literal", [] and
+// [attribute="regex" & ...], each optionally marked as target with @.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use num_traits::FromPrimitive;
+use regex::Regex;
+use crate::*;
+
+//  ***
+//  ***   Requests and responses
+//  ***
+
+#[derive(Debug)]
+pub struct Request {
+    pub command: COMMANDS,
+    pub args: Vec<CQiValue>,
+}
+
+// Accessors panic if the argument doesn't have the type listed for the
+// command in COMMANDS, which the server already checked while reading it.
+impl Request {
+    pub fn byte(&self, i: usize) -> BYTE {
+        match &self.args[i] {
+            CQiValue::Byte(b) => *b,
+            a => panic!("argument {} of {:?} is {:?}, not a BYTE", i, self.command, a),
+        }
+    }
+
+    pub fn int(&self, i: usize) -> INT {
+        match &self.args[i] {
+            CQiValue::Int(n) => *n,
+            a => panic!("argument {} of {:?} is {:?}, not an INT", i, self.command, a),
+        }
+    }
+
+    pub fn string(&self, i: usize) -> &str {
+        match &self.args[i] {
+            CQiValue::String(s) => s,
+            a => panic!("argument {} of {:?} is {:?}, not a STRING", i, self.command, a),
+        }
+    }
+
+    pub fn int_list(&self, i: usize) -> &[INT] {
+        match &self.args[i] {
+            CQiValue::IntList(l) => l,
+            a => panic!("argument {} of {:?} is {:?}, not an INT_LIST", i, self.command, a),
+        }
+    }
+
+    pub fn string_list(&self, i: usize) -> &[STRING] {
+        match &self.args[i] {
+            CQiValue::StringList(l) => l,
+            a => panic!("argument {} of {:?} is {:?}, not a STRING_LIST", i, self.command, a),
+        }
+    }
+}
+
+pub enum Response {
+    Status(STATUS),
+    Error(ERROR),
+    ClError(CL_ERROR),
+    CqpError(CQP_ERROR),
+    Data(CQiValue),
+    // CTRL_USER_ABORT doesn't get an answer
+    Nothing,
+}
+
+impl Response {
+    pub fn bool(value: BOOL) -> Response {
+        Response::Data(CQiValue::Bool(value))
+    }
+
+    pub fn int(value: INT) -> Response {
+        Response::Data(CQiValue::Int(value))
+    }
+
+    pub fn string(value: STRING) -> Response {
+        Response::Data(CQiValue::String(value))
+    }
+
+    pub fn int_list(value: INT_LIST) -> Response {
+        Response::Data(CQiValue::IntList(value))
+    }
+
+    pub fn string_list(value: STRING_LIST) -> Response {
+        Response::Data(CQiValue::StringList(value))
+    }
+
+    pub fn int_int(value: INT_INT) -> Response {
+        Response::Data(CQiValue::IntInt(value))
+    }
+
+    pub fn int_int_int_int(value: INT_INT_INT_INT) -> Response {
+        Response::Data(CQiValue::IntIntIntInt(value))
+    }
+
+    pub fn int_table(value: INT_TABLE) -> Response {
+        Response::Data(CQiValue::IntTable(value))
+    }
+
+    // strings are sent in the charset of the corpus the request was about
+    fn write_to(&self, stream: &mut dyn Write, charset: Charset) -> CQiResult<()> {
+        let encode = |s: &str| Ok(RawString(charset.encode(s)?.into_owned()));
+
+        match self {
+            Response::Status(s) => (*s as WORD).write_cqi_bytes(stream),
+            Response::Error(e) => (*e as WORD).write_cqi_bytes(stream),
+            Response::ClError(e) => (*e as WORD).write_cqi_bytes(stream),
+            Response::CqpError(e) => (*e as WORD).write_cqi_bytes(stream),
+            Response::Data(CQiValue::String(s)) => {
+                (DATA::STRING as WORD).write_cqi_bytes(stream)?;
+                encode(s)?.write_cqi_bytes(stream)
+            },
+            Response::Data(CQiValue::StringList(l)) => {
+                (DATA::STRING_LIST as WORD).write_cqi_bytes(stream)?;
+                l.iter().map(|s| encode(s)).collect::<CQiResult<Vec<RawString>>>()?.write_cqi_bytes(stream)
+            },
+            Response::Data(value) => {
+                (value.data_type() as WORD).write_cqi_bytes(stream)?;
+                value.write_cqi_bytes(stream)
+            },
+            Response::Nothing => Ok(()),
+        }
+    }
+}
+
+// argument types of every command, see the INPUT lines in COMMANDS
+fn command_input(command: &COMMANDS) -> &'static [DATA] {
+    use DATA::*;
+    use COMMANDS::*;
+
+    match command {
+        CTRL_CONNECT => &[STRING, STRING],
+        CTRL_BYE | CTRL_USER_ABORT | CTRL_PING | CTRL_LAST_GENERAL_ERROR => &[],
+        ASK_FEATURE_CQI_1_0 | ASK_FEATURE_CL_2_3 | ASK_FEATURE_CQP_2_3 => &[],
+        CORPUS_LIST_CORPORA => &[],
+        CORPUS_CHARSET | CORPUS_PROPERTIES | CORPUS_POSITIONAL_ATTRIBUTES | CORPUS_STRUCTURAL_ATTRIBUTES
+        | CORPUS_STRUCTURAL_ATTRIBUTE_HAS_VALUES | CORPUS_ALIGNMENT_ATTRIBUTES | CORPUS_FULL_NAME
+        | CORPUS_INFO | CORPUS_DROP_CORPUS => &[STRING],
+        CL_ATTRIBUTE_SIZE | CL_LEXICON_SIZE | CL_DROP_ATTRIBUTE => &[STRING],
+        CL_STR2ID => &[STRING, STRING_LIST],
+        CL_ID2STR | CL_ID2FREQ | CL_CPOS2ID | CL_CPOS2STR | CL_CPOS2STRUC | CL_CPOS2LBOUND
+        | CL_CPOS2RBOUND | CL_CPOS2ALG | CL_STRUC2STR | CL_IDLIST2CPOS => &[STRING, INT_LIST],
+        CL_ID2CPOS | CL_STRUC2CPOS | CL_ALG2CPOS => &[STRING, INT],
+        CL_REGEX2ID => &[STRING, STRING],
+        CQP_QUERY => &[STRING, STRING, STRING],
+        CQP_LIST_SUBCORPORA | CQP_SUBCORPUS_SIZE | CQP_DROP_SUBCORPUS => &[STRING],
+        CQP_SUBCORPUS_HAS_FIELD => &[STRING, BYTE],
+        CQP_DUMP_SUBCORPUS => &[STRING, BYTE, INT, INT],
+        CQP_FDIST_1 => &[STRING, INT, BYTE, STRING],
+        CQP_FDIST_2 => &[STRING, INT, BYTE, STRING, BYTE, STRING],
+    }
+}
+
+//  ***
+//  ***   Fixture corpora
+//  ***
+
+pub struct MockCorpus {
+    name: String,
+    charset: String,
+    full_name: String,
+    properties: Vec<String>,
+    info: Vec<String>,
+    p_attributes: Vec<PAttribute>,
+    s_attributes: Vec<SAttribute>,
+    a_attributes: Vec<(String, Vec<INT_INT_INT_INT>)>,
+}
+
+struct PAttribute {
+    name: String,
+    lexicon: Vec<String>,
+    ids: Vec<INT>,
+}
+
+struct SAttribute {
+    name: String,
+    regions: Vec<INT_INT>,
+    values: Option<Vec<String>>,
+}
+
+impl PAttribute {
+    fn new(name: &str, tokens: &[&str]) -> PAttribute {
+        let mut lexicon: Vec<String> = Vec::new();
+        let mut index: HashMap<&str, INT> = HashMap::new();
+
+        // ids are assigned in order of first occurrence, like cwb-encode does
+        let ids = tokens.iter().map(|token| {
+            *index.entry(token).or_insert_with(|| {
+                lexicon.push(token.to_string());
+                lexicon.len() as INT - 1
+            })
+        }).collect();
+
+        PAttribute { name: name.to_owned(), lexicon, ids }
+    }
+
+    fn frequency(&self, id: INT) -> INT {
+        self.ids.iter().filter(|&&x| x == id).count() as INT
+    }
+
+    fn str(&self, cpos: INT) -> Option<&str> {
+        let id = *self.ids.get(usize::try_from(cpos).ok()?)?;
+        Some(&self.lexicon[id as usize])
+    }
+}
+
+impl SAttribute {
+    fn find(&self, cpos: INT) -> Option<usize> {
+        self.regions.iter().position(|r| r[0] <= cpos && cpos <= r[1])
+    }
+}
+
+impl MockCorpus {
+    pub fn new(name: &str) -> MockCorpus {
+        MockCorpus {
+            name: name.to_owned(),
+            charset: "utf8".to_owned(),
+            full_name: name.to_owned(),
+            properties: vec!["charset = \"utf8\"".to_owned()],
+            info: Vec::new(),
+            p_attributes: Vec::new(),
+            s_attributes: Vec::new(),
+            a_attributes: Vec::new(),
+        }
+    }
+
+    pub fn full_name(mut self, full_name: &str) -> MockCorpus {
+        self.full_name = full_name.to_owned();
+        self
+    }
+
+    pub fn charset(mut self, charset: &str) -> MockCorpus {
+        self.charset = charset.to_owned();
+        self.properties = vec![format!("charset = \"{}\"", charset)];
+        self
+    }
+
+    pub fn info(mut self, lines: &[&str]) -> MockCorpus {
+        self.info = lines.iter().map(|l| l.to_string()).collect();
+        self
+    }
+
+    // All positional attributes must have the same number of tokens.
+    pub fn p_attribute(mut self, name: &str, tokens: &[&str]) -> MockCorpus {
+        self.p_attributes.push(PAttribute::new(name, tokens));
+        self
+    }
+
+    pub fn s_attribute(mut self, name: &str, regions: &[INT_INT]) -> MockCorpus {
+        self.s_attributes.push(SAttribute { name: name.to_owned(), regions: regions.to_vec(), values: None });
+        self
+    }
+
+    pub fn s_attribute_with_values(mut self, name: &str, regions: &[(INT_INT, &str)]) -> MockCorpus {
+        self.s_attributes.push(SAttribute {
+            name: name.to_owned(),
+            regions: regions.iter().map(|(r, _)| *r).collect(),
+            values: Some(regions.iter().map(|(_, v)| v.to_string()).collect()),
+        });
+        self
+    }
+
+    pub fn a_attribute(mut self, name: &str, alignments: &[INT_INT_INT_INT]) -> MockCorpus {
+        self.a_attributes.push((name.to_owned(), alignments.to_vec()));
+        self
+    }
+
+    // Corpus TEST with 22 tokens in 4 sentences (s) and 2 texts (text,
+    // text_genre, text_year) annotated with word, pos and lemma:
+    //
+    //     The cat sat on the mat . The dog barked .   | fiction 1900
+    //     A cat saw the dog . The mat was red .       | news 2000
+    pub fn fixture() -> MockCorpus {
+        MockCorpus::new("TEST")
+            .full_name("Test corpus")
+            .info(&["Test corpus", "for cqi_rs"])
+            .p_attribute("word", &[
+                "The", "cat", "sat", "on", "the", "mat", ".", "The", "dog", "barked", ".",
+                "A", "cat", "saw", "the", "dog", ".", "The", "mat", "was", "red", ".",
+            ])
+            .p_attribute("pos", &[
+                "DT", "NN", "VBD", "IN", "DT", "NN", "SENT", "DT", "NN", "VBD", "SENT",
+                "DT", "NN", "VBD", "DT", "NN", "SENT", "DT", "NN", "VBD", "JJ", "SENT",
+            ])
+            .p_attribute("lemma", &[
+                "the", "cat", "sit", "on", "the", "mat", ".", "the", "dog", "bark", ".",
+                "a", "cat", "see", "the", "dog", ".", "the", "mat", "be", "red", ".",
+            ])
+            .s_attribute("s", &[[0, 6], [7, 10], [11, 16], [17, 21]])
+            .s_attribute("text", &[[0, 10], [11, 21]])
+            .s_attribute_with_values("text_genre", &[([0, 10], "fiction"), ([11, 21], "news")])
+            .s_attribute_with_values("text_year", &[([0, 10], "1900"), ([11, 21], "2000")])
+    }
+
+    fn size(&self) -> INT {
+        self.p_attributes.first().map_or(0, |p| p.ids.len() as INT)
+    }
+
+    fn p_attribute_named(&self, name: &str) -> Option<&PAttribute> {
+        self.p_attributes.iter().find(|p| p.name == name)
+    }
+}
+
+//  ***
+//  ***   Server
+//  ***
+
+type Handler = Box<dyn FnMut(&Request) -> Response + Send>;
+
+#[derive(Debug, Clone)]
+struct MockSubcorpus {
+    matches: Vec<INT_INT>,
+    targets: Option<Vec<INT>>,
+}
+
+pub struct MockServer {
+    corpora: Vec<MockCorpus>,
+    credentials: Option<(String, String)>,
+    handlers: HashMap<WORD, Handler>,
+    subcorpora: BTreeMap<String, MockSubcorpus>,
+    last_error: String,
+    // every command the server has answered, for assertions in tests
+    log: Arc<Mutex<Vec<COMMANDS>>>,
+}
+
+impl Default for MockServer {
+    fn default() -> MockServer {
+        MockServer::new()
+    }
+}
+
+impl MockServer {
+    pub fn new() -> MockServer {
+        MockServer {
+            corpora: Vec::new(),
+            credentials: None,
+            handlers: HashMap::new(),
+            subcorpora: BTreeMap::new(),
+            last_error: String::new(),
+            log: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn corpus(mut self, corpus: MockCorpus) -> MockServer {
+        self.corpora.push(corpus);
+        self
+    }
+
+    // Without credentials every login is accepted.
+    pub fn credentials(mut self, user: &str, password: &str) -> MockServer {
+        self.credentials = Some((user.to_owned(), password.to_owned()));
+        self
+    }
+
+    // Answers <command> with the handler instead of the built-in behaviour.
+    pub fn on<F>(mut self, command: COMMANDS, handler: F) -> MockServer
+        where F: FnMut(&Request) -> Response + Send + 'static
+    {
+        self.handlers.insert(command as WORD, Box::new(handler));
+        self
+    }
+
+    // Answers <command> with the given responses in order, and with
+    // ERROR::GENERAL_ERROR once they are used up.
+    pub fn script(self, command: COMMANDS, responses: Vec<Response>) -> MockServer {
+        let mut responses: VecDeque<Response> = responses.into();
+        self.on(command, move |_| responses.pop_front().unwrap_or(Response::Error(ERROR::GENERAL_ERROR)))
+    }
+
+    pub fn log(&self) -> Vec<COMMANDS> {
+        self.log.lock().unwrap().clone()
+    }
+
+    // Serves connections one after another on a local port in a background thread.
+    pub fn spawn(mut self) -> CQiResult<MockHandle> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let log = self.log.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let _ = stream.set_nodelay(true);
+                        let _ = self.serve(stream);
+                    },
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Ok(MockHandle { addr, log })
+    }
+
+    // Serves a single client over any transport until it says CTRL_BYE or
+    // closes the connection.
+    pub fn serve<S: Read + Write>(&mut self, stream: S) -> CQiResult<()> {
+        let mut stream = BufReader::new(stream);
+        let limits = DecodeLimits::default();
+
+        loop {
+            let word = match WORD::read_cqi_bytes(&mut stream, &limits) {
+                Ok(word) => word,
+                Err(CQiError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+
+            let command = match COMMANDS::from_u16(word) {
+                Some(command) => command,
+                None => {
+                    // the arguments can't be skipped, so give up on the client
+                    Response::Error(ERROR::SYNTAX_ERROR).write_to(stream.get_mut(), Charset::Utf8)?;
+                    return Ok(());
+                },
+            };
+
+            // the first argument names the corpus whose charset the
+            // remaining strings are in
+            let mut charset = Charset::Utf8;
+            let mut args = Vec::new();
+            for (i, &datatype) in command_input(&command).iter().enumerate() {
+                let arg = match datatype {
+                    DATA::STRING => {
+                        let raw = RawString::read_cqi_bytes(&mut stream, &limits)?;
+                        CQiValue::String(charset.decode(raw.0)?)
+                    },
+                    DATA::STRING_LIST => {
+                        let raw = Vec::<RawString>::read_cqi_bytes(&mut stream, &limits)?;
+                        CQiValue::StringList(raw.into_iter().map(|s| charset.decode(s.0)).collect::<CQiResult<_>>()?)
+                    },
+                    _ => CQiValue::read_cqi_value(datatype, &mut stream, &limits)?,
+                };
+
+                if let (0, CQiValue::String(name)) = (i, &arg) {
+                    charset = self.charset_of(name);
+                }
+                args.push(arg);
+            }
+
+            let request = Request { command, args };
+            let response = self.respond(&request);
+            self.log.lock().unwrap().push(request.command);
+
+            let mut out = BufWriter::new(stream.get_mut());
+            response.write_to(&mut out, charset)?;
+            out.flush()?;
+
+            if let COMMANDS::CTRL_BYE = request.command {
+                return Ok(());
+            }
+        }
+    }
+
+    fn respond(&mut self, request: &Request) -> Response {
+        if let Some(handler) = self.handlers.get_mut(&(request.command as WORD)) {
+            return handler(request);
+        }
+
+        match self.builtin(request) {
+            Ok(response) => response,
+            Err(response) => response,
+        }
+    }
+
+    // charset of the corpus an argument like "TEST", "TEST.word" or "TEST:Last" refers to
+    fn charset_of(&self, name: &str) -> Charset {
+        let corpus = charset::corpus_of(name);
+        self.corpora.iter()
+            .find(|c| c.name == corpus)
+            .and_then(|c| Charset::from_name(&c.charset))
+            .unwrap_or(Charset::Utf8)
+    }
+
+    fn corpus_named(&self, name: &str) -> Result<&MockCorpus, Response> {
+        match self.corpora.iter().find(|c| c.name == name) {
+            Some(corpus) => Ok(corpus),
+            None => Err(Response::CqpError(CQP_ERROR::NO_SUCH_CORPUS)),
+        }
+    }
+
+    // splits "CORPUS.attribute"
+    fn attribute<'a>(&'a self, specifier: &'a str) -> Result<(&'a MockCorpus, &'a str), Response> {
+        let mut parts = specifier.splitn(2, '.');
+        let corpus = parts.next().unwrap_or("");
+        let attribute = parts.next().ok_or(Response::Error(ERROR::SYNTAX_ERROR))?;
+
+        match self.corpora.iter().find(|c| c.name == corpus) {
+            Some(corpus) => Ok((corpus, attribute)),
+            None => Err(Response::ClError(CL_ERROR::NO_SUCH_ATTRIBUTE)),
+        }
+    }
+
+    fn p_attribute<'a>(&'a self, specifier: &'a str) -> Result<(&'a MockCorpus, &'a PAttribute), Response> {
+        let (corpus, name) = self.attribute(specifier)?;
+
+        match corpus.p_attribute_named(name) {
+            Some(p) => Ok((corpus, p)),
+            None => Err(self.wrong_type(corpus, name)),
+        }
+    }
+
+    fn s_attribute<'a>(&'a self, specifier: &'a str) -> Result<&'a SAttribute, Response> {
+        let (corpus, name) = self.attribute(specifier)?;
+
+        match corpus.s_attributes.iter().find(|s| s.name == name) {
+            Some(s) => Ok(s),
+            None => Err(self.wrong_type(corpus, name)),
+        }
+    }
+
+    fn a_attribute<'a>(&'a self, specifier: &'a str) -> Result<&'a [INT_INT_INT_INT], Response> {
+        let (corpus, name) = self.attribute(specifier)?;
+
+        match corpus.a_attributes.iter().find(|a| a.0 == name) {
+            Some(a) => Ok(&a.1),
+            None => Err(self.wrong_type(corpus, name)),
+        }
+    }
+
+    fn wrong_type(&self, corpus: &MockCorpus, name: &str) -> Response {
+        let exists = corpus.p_attributes.iter().any(|p| p.name == name)
+            || corpus.s_attributes.iter().any(|s| s.name == name)
+            || corpus.a_attributes.iter().any(|a| a.0 == name);
+
+        if exists {
+            Response::ClError(CL_ERROR::WRONG_ATTRIBUTE_TYPE)
+        } else {
+            Response::ClError(CL_ERROR::NO_SUCH_ATTRIBUTE)
+        }
+    }
+
+    fn subcorpus(&self, name: &str) -> Result<&MockSubcorpus, Response> {
+        match self.subcorpora.get(name) {
+            Some(subcorpus) => Ok(subcorpus),
+            None => Err(Response::CqpError(CQP_ERROR::NO_SUCH_CORPUS)),
+        }
+    }
+
+    fn builtin(&mut self, request: &Request) -> Result<Response, Response> {
+        use COMMANDS::*;
+
+        let r = request;
+
+        Ok(match r.command {
+            CTRL_CONNECT => {
+                let accepted = match &self.credentials {
+                    Some((user, password)) => user == r.string(0) && password == r.string(1),
+                    None => true,
+                };
+
+                if accepted {
+                    Response::Status(STATUS::CONNECT_OK)
+                } else {
+                    Response::Error(ERROR::CONNECT_REFUSED)
+                }
+            },
+            CTRL_BYE => Response::Status(STATUS::BYE_OK),
+            CTRL_USER_ABORT => Response::Nothing,
+            CTRL_PING => Response::Status(STATUS::PING_OK),
+            CTRL_LAST_GENERAL_ERROR => Response::string(self.last_error.clone()),
+            ASK_FEATURE_CQI_1_0 | ASK_FEATURE_CL_2_3 | ASK_FEATURE_CQP_2_3 => Response::bool(true),
+
+            CORPUS_LIST_CORPORA => Response::string_list(self.corpora.iter().map(|c| c.name.clone()).collect()),
+            CORPUS_CHARSET => Response::string(self.corpus_named(r.string(0))?.charset.clone()),
+            CORPUS_PROPERTIES => Response::string_list(self.corpus_named(r.string(0))?.properties.clone()),
+            CORPUS_POSITIONAL_ATTRIBUTES => {
+                let corpus = self.corpus_named(r.string(0))?;
+                Response::string_list(corpus.p_attributes.iter().map(|p| p.name.clone()).collect())
+            },
+            CORPUS_STRUCTURAL_ATTRIBUTES => {
+                let corpus = self.corpus_named(r.string(0))?;
+                Response::string_list(corpus.s_attributes.iter().map(|s| s.name.clone()).collect())
+            },
+            CORPUS_STRUCTURAL_ATTRIBUTE_HAS_VALUES => Response::bool(self.s_attribute(r.string(0))?.values.is_some()),
+            CORPUS_ALIGNMENT_ATTRIBUTES => {
+                let corpus = self.corpus_named(r.string(0))?;
+                Response::string_list(corpus.a_attributes.iter().map(|a| a.0.clone()).collect())
+            },
+            CORPUS_FULL_NAME => Response::string(self.corpus_named(r.string(0))?.full_name.clone()),
+            CORPUS_INFO => Response::string_list(self.corpus_named(r.string(0))?.info.clone()),
+            CORPUS_DROP_CORPUS => {
+                self.corpus_named(r.string(0))?;
+                Response::Status(STATUS::OK)
+            },
+
+            CL_ATTRIBUTE_SIZE => {
+                let (corpus, name) = self.attribute(r.string(0))?;
+
+                if let Some(p) = corpus.p_attribute_named(name) {
+                    Response::int(p.ids.len() as INT)
+                } else if let Ok(s) = self.s_attribute(r.string(0)) {
+                    Response::int(s.regions.len() as INT)
+                } else {
+                    Response::int(self.a_attribute(r.string(0))?.len() as INT)
+                }
+            },
+            CL_LEXICON_SIZE => Response::int(self.p_attribute(r.string(0))?.1.lexicon.len() as INT),
+            CL_DROP_ATTRIBUTE => {
+                let (corpus, name) = self.attribute(r.string(0))?;
+                match self.wrong_type(corpus, name) {
+                    Response::ClError(CL_ERROR::NO_SUCH_ATTRIBUTE) => return Err(Response::ClError(CL_ERROR::NO_SUCH_ATTRIBUTE)),
+                    _ => Response::Status(STATUS::OK),
+                }
+            },
+            CL_STR2ID => {
+                let p = self.p_attribute(r.string(0))?.1;
+                Response::int_list(r.string_list(1).iter().map(|s| {
+                    p.lexicon.iter().position(|l| l == s).map_or(-1, |id| id as INT)
+                }).collect())
+            },
+            CL_ID2STR => {
+                let p = self.p_attribute(r.string(0))?.1;
+                Response::string_list(r.int_list(1).iter().map(|&id| {
+                    usize::try_from(id).ok().and_then(|id| p.lexicon.get(id)).cloned().unwrap_or_default()
+                }).collect())
+            },
+            CL_ID2FREQ => {
+                let p = self.p_attribute(r.string(0))?.1;
+                Response::int_list(r.int_list(1).iter().map(|&id| p.frequency(id)).collect())
+            },
+            CL_CPOS2ID => {
+                let p = self.p_attribute(r.string(0))?.1;
+                Response::int_list(r.int_list(1).iter().map(|&cpos| {
+                    usize::try_from(cpos).ok().and_then(|cpos| p.ids.get(cpos)).copied().unwrap_or(-1)
+                }).collect())
+            },
+            CL_CPOS2STR => {
+                let p = self.p_attribute(r.string(0))?.1;
+                Response::string_list(r.int_list(1).iter().map(|&cpos| p.str(cpos).unwrap_or("").to_owned()).collect())
+            },
+            CL_CPOS2STRUC => {
+                let s = self.s_attribute(r.string(0))?;
+                Response::int_list(r.int_list(1).iter().map(|&cpos| s.find(cpos).map_or(-1, |i| i as INT)).collect())
+            },
+            CL_CPOS2LBOUND => {
+                let s = self.s_attribute(r.string(0))?;
+                Response::int_list(r.int_list(1).iter().map(|&cpos| s.find(cpos).map_or(-1, |i| s.regions[i][0])).collect())
+            },
+            CL_CPOS2RBOUND => {
+                let s = self.s_attribute(r.string(0))?;
+                Response::int_list(r.int_list(1).iter().map(|&cpos| s.find(cpos).map_or(-1, |i| s.regions[i][1])).collect())
+            },
+            CL_CPOS2ALG => {
+                let a = self.a_attribute(r.string(0))?;
+                Response::int_list(r.int_list(1).iter().map(|&cpos| {
+                    a.iter().position(|alg| alg[0] <= cpos && cpos <= alg[1]).map_or(-1, |i| i as INT)
+                }).collect())
+            },
+            CL_STRUC2STR => {
+                let s = self.s_attribute(r.string(0))?;
+                let values = s.values.as_ref().ok_or(Response::ClError(CL_ERROR::CORPUS_ACCESS))?;
+                Response::string_list(r.int_list(1).iter().map(|&struc| {
+                    usize::try_from(struc).ok().and_then(|i| values.get(i)).cloned().unwrap_or_default()
+                }).collect())
+            },
+            CL_ID2CPOS => {
+                let p = self.p_attribute(r.string(0))?.1;
+                let id = r.int(1);
+
+                if id < 0 || id as usize >= p.lexicon.len() {
+                    return Err(Response::ClError(CL_ERROR::OUT_OF_RANGE));
+                }
+
+                Response::int_list(positions(p, |x| x == id))
+            },
+            CL_IDLIST2CPOS => {
+                let p = self.p_attribute(r.string(0))?.1;
+                let ids = r.int_list(1);
+                Response::int_list(positions(p, |x| ids.contains(&x)))
+            },
+            CL_REGEX2ID => {
+                let p = self.p_attribute(r.string(0))?.1;
+                let regex = anchored(r.string(1)).map_err(|_| Response::ClError(CL_ERROR::REGEX))?;
+                Response::int_list((0..p.lexicon.len() as INT).filter(|&id| regex.is_match(&p.lexicon[id as usize])).collect())
+            },
+            CL_STRUC2CPOS => {
+                let s = self.s_attribute(r.string(0))?;
+                let region = usize::try_from(r.int(1)).ok().and_then(|i| s.regions.get(i));
+                Response::int_int(*region.ok_or(Response::ClError(CL_ERROR::OUT_OF_RANGE))?)
+            },
+            CL_ALG2CPOS => {
+                let a = self.a_attribute(r.string(0))?;
+                let alignment = usize::try_from(r.int(1)).ok().and_then(|i| a.get(i));
+                Response::int_int_int_int(*alignment.ok_or(Response::ClError(CL_ERROR::OUT_OF_RANGE))?)
+            },
+
+            CQP_QUERY => {
+                let corpus = self.corpus_named(r.string(0))?;
+
+                match run_query(corpus, r.string(2)) {
+                    Ok(subcorpus) => {
+                        let name = format!("{}:{}", r.string(0), r.string(1));
+                        self.subcorpora.insert(name, subcorpus);
+                        Response::Status(STATUS::OK)
+                    },
+                    Err(message) => {
+                        self.last_error = message;
+                        Response::CqpError(CQP_ERROR::GENERAL)
+                    },
+                }
+            },
+            CQP_LIST_SUBCORPORA => {
+                self.corpus_named(r.string(0))?;
+                let prefix = format!("{}:", r.string(0));
+                Response::string_list(self.subcorpora.keys()
+                    .filter_map(|name| name.strip_prefix(&prefix))
+                    .map(|name| name.to_owned())
+                    .collect())
+            },
+            CQP_SUBCORPUS_SIZE => Response::int(self.subcorpus(r.string(0))?.matches.len() as INT),
+            CQP_SUBCORPUS_HAS_FIELD => {
+                let subcorpus = self.subcorpus(r.string(0))?;
+                match FIELD::from_u8(r.byte(1)) {
+                    Some(field) => Response::bool(field_values(subcorpus, field).is_some()),
+                    None => return Err(Response::CqpError(CQP_ERROR::INVALID_FIELD)),
+                }
+            },
+            CQP_DUMP_SUBCORPUS => {
+                let subcorpus = self.subcorpus(r.string(0))?;
+                let values = FIELD::from_u8(r.byte(1))
+                    .and_then(|field| field_values(subcorpus, field))
+                    .ok_or(Response::CqpError(CQP_ERROR::INVALID_FIELD))?;
+                let (first, last) = (r.int(2), r.int(3));
+
+                if first < 0 || first > last || last as usize >= values.len() {
+                    return Err(Response::CqpError(CQP_ERROR::OUT_OF_RANGE));
+                }
+
+                Response::int_list(values[first as usize..=last as usize].to_vec())
+            },
+            CQP_DROP_SUBCORPUS => {
+                match self.subcorpora.remove(r.string(0)) {
+                    Some(_) => Response::Status(STATUS::OK),
+                    None => return Err(Response::CqpError(CQP_ERROR::NO_SUCH_CORPUS)),
+                }
+            },
+            CQP_FDIST_1 => {
+                let subcorpus = self.subcorpus(r.string(0))?;
+                let cpos = FIELD::from_u8(r.byte(2))
+                    .and_then(|field| field_values(subcorpus, field))
+                    .ok_or(Response::CqpError(CQP_ERROR::INVALID_FIELD))?;
+                let p = self.p_attribute(r.string(3))?.1;
+
+                let mut counts: HashMap<INT, INT> = HashMap::new();
+                for c in cpos.into_iter().filter(|&c| c >= 0) {
+                    *counts.entry(p.ids[c as usize]).or_insert(0) += 1;
+                }
+
+                Response::int_table(fdist_table(counts, r.int(1), |id| vec![id]))
+            },
+            CQP_FDIST_2 => {
+                let subcorpus = self.subcorpus(r.string(0))?;
+                let cpos1 = FIELD::from_u8(r.byte(2))
+                    .and_then(|field| field_values(subcorpus, field))
+                    .ok_or(Response::CqpError(CQP_ERROR::INVALID_FIELD))?;
+                let p1 = self.p_attribute(r.string(3))?.1;
+                let cpos2 = FIELD::from_u8(r.byte(4))
+                    .and_then(|field| field_values(subcorpus, field))
+                    .ok_or(Response::CqpError(CQP_ERROR::INVALID_FIELD))?;
+                let p2 = self.p_attribute(r.string(5))?.1;
+
+                let mut counts: HashMap<(INT, INT), INT> = HashMap::new();
+                for (c1, c2) in cpos1.into_iter().zip(cpos2).filter(|&(c1, c2)| c1 >= 0 && c2 >= 0) {
+                    *counts.entry((p1.ids[c1 as usize], p2.ids[c2 as usize])).or_insert(0) += 1;
+                }
+
+                Response::int_table(fdist_table(counts, r.int(1), |(id1, id2)| vec![id1, id2]))
+            },
+        })
+    }
+}
+
+pub struct MockHandle {
+    addr: SocketAddr,
+    log: Arc<Mutex<Vec<COMMANDS>>>,
+}
+
+impl MockHandle {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // commands answered so far, over all connections
+    pub fn log(&self) -> Vec<COMMANDS> {
+        self.log.lock().unwrap().clone()
+    }
+
+    pub fn connect(&self) -> CQiResult<CQiConnection> {
+        CQiConnection::new(self.addr)
+    }
+}
+
+fn positions<F: Fn(INT) -> bool>(p: &PAttribute, pred: F) -> INT_LIST {
+    (0..p.ids.len() as INT).filter(|&cpos| pred(p.ids[cpos as usize])).collect()
+}
+
+fn anchored(regex: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", regex))
+}
+
+fn field_values(subcorpus: &MockSubcorpus, field: FIELD) -> Option<INT_LIST> {
+    match field {
+        FIELD::MATCH => Some(subcorpus.matches.iter().map(|m| m[0]).collect()),
+        FIELD::MATCHEND => Some(subcorpus.matches.iter().map(|m| m[1]).collect()),
+        FIELD::TARGET_0 => subcorpus.targets.clone(),
+        _ => None,
+    }
+}
+
+// rows with at least <cutoff> occurrences, by frequency desc
+fn fdist_table<K: Ord + Copy, F: Fn(K) -> Vec<INT>>(counts: HashMap<K, INT>, cutoff: INT, row: F) -> INT_TABLE {
+    let mut counts: Vec<(K, INT)> = counts.into_iter().filter(|&(_, f)| f >= cutoff).collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    counts.into_iter().map(|(key, f)| {
+        let mut row = row(key);
+        row.push(f);
+        row
+    }).collect()
+}
+
+struct TokenPattern {
+    target: bool,
+    conditions: Vec<(String, Regex)>,
+}
+
+fn run_query(corpus: &MockCorpus, query: &str) -> Result<MockSubcorpus, String> {
+    let patterns = parse_query(query)?;
+
+    for pattern in &patterns {
+        for (attribute, _) in &pattern.conditions {
+            if corpus.p_attribute_named(attribute).is_none() {
+                return Err(format!("positional attribute {} does not exist", attribute));
+            }
+        }
+    }
+
+    let len = patterns.len() as INT;
+    let target = patterns.iter().position(|p| p.target);
+    let mut matches = Vec::new();
+
+    for start in 0..=(corpus.size() - len) {
+        let is_match = patterns.iter().enumerate().all(|(i, pattern)| {
+            pattern.conditions.iter().all(|(attribute, regex)| {
+                let p = corpus.p_attribute_named(attribute).unwrap();
+                regex.is_match(p.str(start + i as INT).unwrap_or(""))
+            })
+        });
+
+        if is_match {
+            matches.push([start, start + len - 1]);
+        }
+    }
+
+    let targets = target.map(|t| matches.iter().map(|m| m[0] + t as INT).collect());
+
+    Ok(MockSubcorpus { matches, targets })
+}
+
+fn parse_query(query: &str) -> Result<Vec<TokenPattern>, String> {
+    let query = query.trim();
+    let body = query.strip_suffix(';').ok_or_else(|| "query must be terminated by ';'".to_owned())?;
+    let mut rest = body.trim_start();
+    let mut patterns = Vec::new();
+
+    while !rest.is_empty() {
+        let mut target = false;
+        if let Some(r) = rest.strip_prefix('@') {
+            target = true;
+            rest = r;
+        }
+
+        let conditions = if rest.starts_with('"') {
+            let (literal, r) = quoted(rest)?;
+            rest = r;
+            vec![("word".to_owned(), anchored(&literal).map_err(|e| e.to_string())?)]
+        } else if let Some(r) = rest.strip_prefix('[') {
+            let end = r.find(']').ok_or_else(|| "missing ']'".to_owned())?;
+            rest = &r[end + 1..];
+            let inner = r[..end].trim();
+
+            let mut conditions = Vec::new();
+            if !inner.is_empty() {
+                for condition in inner.split('&') {
+                    let mut parts = condition.splitn(2, '=');
+                    let attribute = parts.next().unwrap().trim();
+                    let value = parts.next().ok_or_else(|| format!("invalid condition {}", condition.trim()))?.trim();
+                    let (value, tail) = quoted(value)?;
+                    if !tail.trim().is_empty() {
+                        return Err(format!("invalid condition {}", condition.trim()));
+                    }
+                    conditions.push((attribute.to_owned(), anchored(&value).map_err(|e| e.to_string())?));
+                }
+            }
+            conditions
+        } else {
+            return Err(format!("syntax error near \"{}\"", rest));
+        };
+
+        patterns.push(TokenPattern { target, conditions });
+        rest = rest.trim_start();
+    }
+
+    if patterns.is_empty() {
+        return Err("empty query".to_owned());
+    }
+    if patterns.iter().filter(|p| p.target).count() > 1 {
+        return Err("more than one target".to_owned());
+    }
+
+    Ok(patterns)
+}
+
+// splits a leading "..." off <s>
+fn quoted(s: &str) -> Result<(String, &str), String> {
+    let inner = s.strip_prefix('"').ok_or_else(|| format!("expected '\"' in \"{}\"", s))?;
+    let end = inner.find('"').ok_or_else(|| "unterminated string".to_owned())?;
+    Ok((inner[..end].to_owned(), &inner[end + 1..]))
+}
@@ -1,7 +1,1124 @@
-#[cfg(test)]
-mod tests {
+use crate::*;
+
+#[test]
+fn it_works() {
+    assert_eq!(2 + 2, 4);
+}
+
+#[test]
+fn server_errors_are_decoded() {
+    let expected = STATUS::OK as WORD;
+
+    match CQiError::from_response(expected, CL_ERROR::WRONG_ATTRIBUTE_TYPE as WORD) {
+        CQiError::ClError(CL_ERROR::WRONG_ATTRIBUTE_TYPE) => (),
+        e => panic!("unexpected error {:?}", e),
+    }
+
+    match CQiError::from_response(expected, CQP_ERROR::NO_SUCH_CORPUS as WORD) {
+        CQiError::CqpError(CQP_ERROR::NO_SUCH_CORPUS, None) => (),
+        e => panic!("unexpected error {:?}", e),
+    }
+
+    match CQiError::from_response(expected, ERROR::SYNTAX_ERROR as WORD) {
+        CQiError::Error(ERROR::SYNTAX_ERROR, None) => (),
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn protocol_violations_are_detected() {
+    let expected = DATA::INT_LIST as WORD;
+
+    match CQiError::from_response(expected, DATA::STRING_LIST as WORD) {
+        CQiError::UnexpectedResponse { received, .. } => assert_eq!(received, DATA::STRING_LIST as WORD),
+        e => panic!("unexpected error {:?}", e),
+    }
+
+    match CQiError::from_response(expected, 0x0799) {
+        CQiError::UnknownResponse(0x0799) => (),
+        e => panic!("unexpected error {:?}", e),
+    }
+
+    match CQiError::from_response(expected, 0x04FF) {
+        CQiError::UnknownResponse(0x04FF) => (),
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn general_errors_carry_messages() {
+    let error = CQiError::from_response(STATUS::OK as WORD, CQP_ERROR::GENERAL as WORD);
+    assert!(error.is_general());
+    assert_eq!(error.message(), None);
+
+    let error = error.with_message("syntax error".to_string());
+    assert_eq!(error.message(), Some("syntax error"));
+    assert_eq!(error.to_string(), "CQP_ERROR::GENERAL: syntax error");
+
+    let error = CQiError::from_response(STATUS::OK as WORD, CL_ERROR::REGEX as WORD);
+    assert!(!error.is_general());
+    assert_eq!(error.with_message("ignored".to_string()).message(), None);
+}
+
+#[test]
+fn values_survive_a_round_trip() {
+    let values = vec![
+        CQiValue::Byte(0x42),
+        CQiValue::Bool(true),
+        CQiValue::Int(-7),
+        CQiValue::String("Grüße".to_string()),
+        CQiValue::ByteList(vec![1, 2, 3]),
+        CQiValue::BoolList(vec![true, false]),
+        CQiValue::IntList(vec![1, -1, i32::MAX]),
+        CQiValue::StringList(vec!["a".to_string(), String::new()]),
+        CQiValue::IntInt([1, 2]),
+        CQiValue::IntIntIntInt([1, 2, 3, 4]),
+        CQiValue::IntTable(vec![vec![1, 2], vec![3, 4], vec![5, 6]]),
+    ];
+
+    let mut bytes = Vec::new();
+    for value in &values {
+        value.write_cqi_bytes(&mut bytes).unwrap();
+    }
+
+    let mut stream = &bytes[..];
+    for value in &values {
+        assert_eq!(&CQiValue::read_cqi_value(value.data_type(), &mut stream, &DecodeLimits::default()).unwrap(), value);
+    }
+    assert!(stream.is_empty());
+
+    let mut stream = &bytes[1 + 1 + 4..];
+    assert_eq!(STRING::read_cqi_bytes(&mut stream, &DecodeLimits::default()).unwrap(), "Grüße");
+    assert_eq!(BYTE_LIST::read_cqi_bytes(&mut stream, &DecodeLimits::default()).unwrap(), vec![1, 2, 3]);
+}
+
+#[test]
+fn corrupt_lengths_are_rejected() {
+    let limits = DecodeLimits { max_string_len: 4, max_list_len: 10, max_table_cells: 6 };
+
+    // negative length
+    let bytes = (-1i32).to_be_bytes();
+    match INT_LIST::read_cqi_bytes(&mut &bytes[..], &limits) {
+        Err(CQiError::InvalidData(_)) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // huge length with no data behind it, must fail without allocating it
+    let bytes = i32::MAX.to_be_bytes();
+    match STRING_LIST::read_cqi_bytes(&mut &bytes[..], &DecodeLimits::default()) {
+        Err(CQiError::LimitExceeded { what: "list", .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+    let bytes = (1i32 << 27).to_be_bytes();
+    match INT_LIST::read_cqi_bytes(&mut &bytes[..], &DecodeLimits::default()) {
+        Err(CQiError::Io(_)) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    let mut bytes = Vec::new();
+    "hello".to_string().write_cqi_bytes(&mut bytes).unwrap();
+    match STRING::read_cqi_bytes(&mut &bytes[..], &limits) {
+        Err(CQiError::LimitExceeded { what: "string", len: 5, limit: 4 }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // rows * cols overflows i32
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&0x10000i32.to_be_bytes());
+    bytes.extend_from_slice(&0x10000i32.to_be_bytes());
+    match INT_TABLE::read_cqi_bytes(&mut &bytes[..], &DecodeLimits::default()) {
+        Err(CQiError::LimitExceeded { what: "table", .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    let mut response = Vec::new();
+    response.extend_from_slice(&(DATA::INT_TABLE as WORD).to_be_bytes());
+    response.extend_from_slice(&3i32.to_be_bytes());
+    response.extend_from_slice(&3i32.to_be_bytes());
+
+    let mut connection = CQiConnection::from_stream(Pipe::new(response));
+    connection.set_decode_limits(limits);
+    match connection.cqp_fdist_1("TEST:Last", 0, FIELD::MATCH, "word") {
+        Err(CQiError::LimitExceeded { what: "table", len: 9, limit: 6 }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+// in-memory transport replaying a canned server response
+struct Pipe {
+    input: std::io::Cursor<Vec<u8>>,
+    output: Vec<u8>,
+    writes: usize,
+}
+
+impl Pipe {
+    fn new(response: Vec<u8>) -> Pipe {
+        Pipe { input: std::io::Cursor::new(response), output: Vec::new(), writes: 0 }
+    }
+}
+
+impl std::io::Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl std::io::Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writes += 1;
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn encode(values: &[&dyn CQiData]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for value in values {
+        value.write_cqi_bytes(&mut bytes).unwrap();
+    }
+    bytes
+}
+
+#[test]
+fn corpus_commands_are_typed() {
+    let response = [
+        encode(&[&(DATA::STRING_LIST as WORD), &vec!["TEST".to_string()]]),
+        encode(&[&(DATA::STRING as WORD), &"Test corpus"]),
+        encode(&[&(DATA::BOOL as WORD), &true]),
+        encode(&[&(DATA::STRING_LIST as WORD), &vec!["word".to_string()]]),
+    ].concat();
+
+    let connection = CQiConnection::from_stream(Pipe::new(response));
+    connection.set_charset("TEST", Charset::Utf8);
+    assert_eq!(connection.corpus_list_corpora().unwrap(), vec!["TEST"]);
+    assert_eq!(connection.corpus_full_name("TEST").unwrap(), "Test corpus");
+    assert!(connection.corpus_structural_attribute_has_values("TEST.s").unwrap());
+    assert_eq!(connection.corpus_positional_attributes("TEST").unwrap(), vec!["word"]);
+
+    assert_eq!(connection.get_ref().output, encode(&[
+        &(COMMANDS::CORPUS_LIST_CORPORA as WORD),
+        &(COMMANDS::CORPUS_FULL_NAME as WORD), &"TEST",
+        &(COMMANDS::CORPUS_STRUCTURAL_ATTRIBUTE_HAS_VALUES as WORD), &"TEST.s",
+        &(COMMANDS::CORPUS_POSITIONAL_ATTRIBUTES as WORD), &"TEST",
+    ]));
+}
+
+#[test]
+fn cl_commands_are_typed() {
+    let response = [
+        encode(&[&(DATA::INT as WORD), &22]),
+        encode(&[&(DATA::INT_LIST as WORD), &vec![0, 1]]),
+        encode(&[&(DATA::INT_INT as WORD), &[7, 10]]),
+        encode(&[&(DATA::INT_INT_INT_INT as WORD), &[0, 3, 0, 2]]),
+    ].concat();
+
+    let connection = CQiConnection::from_stream(Pipe::new(response));
+    assert_eq!(connection.cl_attribute_size("TEST.word").unwrap(), 22);
+    assert_eq!(connection.cl_cpos2id("TEST.word", &[0, 1]).unwrap(), vec![0, 1]);
+    assert_eq!(connection.cl_struc2cpos("TEST.s", 1).unwrap(), [7, 10]);
+    assert_eq!(connection.cl_alg2cpos("TEST.test_de", 0).unwrap(), [0, 3, 0, 2]);
+
+    assert_eq!(connection.get_ref().output, encode(&[
+        &(COMMANDS::CL_ATTRIBUTE_SIZE as WORD), &"TEST.word",
+        &(COMMANDS::CL_CPOS2ID as WORD), &"TEST.word", &vec![0, 1],
+        &(COMMANDS::CL_STRUC2CPOS as WORD), &"TEST.s", &1,
+        &(COMMANDS::CL_ALG2CPOS as WORD), &"TEST.test_de", &0,
+    ]));
+}
+
+#[test]
+fn cqp_commands_are_typed() {
+    let response = [
+        encode(&[&(STATUS::OK as WORD)]),
+        encode(&[&(DATA::INT as WORD), &2]),
+        encode(&[&(DATA::BOOL as WORD), &false]),
+        encode(&[&(DATA::INT_LIST as WORD), &vec![4, 7]]),
+        encode(&[&(DATA::INT_TABLE as WORD), &vec![vec![0, 1, 2], vec![0, 8, 1]]]),
+    ].concat();
+
+    let connection = CQiConnection::from_stream(Pipe::new(response));
+    connection.set_charset("TEST", Charset::Utf8);
+    connection.cqp_query("TEST", "Det", "\"the\";").unwrap();
+    assert_eq!(connection.cqp_subcorpus_size("TEST:Det").unwrap(), 2);
+    assert!(!connection.cqp_subcorpus_has_field("TEST:Det", FIELD::TARGET).unwrap());
+    assert_eq!(connection.cqp_dump_subcorpus("TEST:Det", FIELD::MATCH, 0, 1).unwrap(), vec![4, 7]);
+    let pairs = connection.cqp_fdist_2("TEST:Det", 1, FIELD::MATCH, "TEST.word", FIELD::MATCHEND, "TEST.word").unwrap();
+    assert_eq!(pairs, vec![FdistPair { id1: 0, id2: 1, frequency: 2 }, FdistPair { id1: 0, id2: 8, frequency: 1 }]);
+
+    assert_eq!(connection.get_ref().output, encode(&[
+        &(COMMANDS::CQP_QUERY as WORD), &"TEST", &"Det", &"\"the\";",
+        &(COMMANDS::CQP_SUBCORPUS_SIZE as WORD), &"TEST:Det",
+        &(COMMANDS::CQP_SUBCORPUS_HAS_FIELD as WORD), &"TEST:Det", &(FIELD::TARGET as BYTE),
+        &(COMMANDS::CQP_DUMP_SUBCORPUS as WORD), &"TEST:Det", &(FIELD::MATCH as BYTE), &0, &1,
+        &(COMMANDS::CQP_FDIST_2 as WORD), &"TEST:Det", &1, &(FIELD::MATCH as BYTE), &"TEST.word",
+        &(FIELD::MATCHEND as BYTE), &"TEST.word",
+    ]));
+}
+
+#[test]
+fn commands_work_over_any_stream() {
+    let mut response = Vec::new();
+    response.extend_from_slice(&(DATA::INT_LIST as WORD).to_be_bytes());
+    response.extend_from_slice(&2i32.to_be_bytes());
+    response.extend_from_slice(&17i32.to_be_bytes());
+    response.extend_from_slice(&(-1i32).to_be_bytes());
+
+    let connection = CQiConnection::from_stream(Pipe::new(response));
+    connection.set_charset("TEST", Charset::Utf8);
+    let ids = connection.cl_str2id("TEST.word", &["the", "xyzzy"]).unwrap();
+    assert_eq!(ids, vec![17, -1]);
+
+    let mut request = Vec::new();
+    request.extend_from_slice(&(COMMANDS::CL_STR2ID as WORD).to_be_bytes());
+    request.extend_from_slice(&[0, 9]);
+    request.extend_from_slice(b"TEST.word");
+    request.extend_from_slice(&2i32.to_be_bytes());
+    request.extend_from_slice(&[0, 3]);
+    request.extend_from_slice(b"the");
+    request.extend_from_slice(&[0, 5]);
+    request.extend_from_slice(b"xyzzy");
+    assert_eq!(connection.get_ref().output, request);
+}
+
+#[test]
+fn error_responses_are_returned() {
+    let response = (CL_ERROR::NO_SUCH_ATTRIBUTE as WORD).to_be_bytes().to_vec();
+
+    let connection = CQiConnection::from_stream(Pipe::new(response));
+    match connection.cl_lexicon_size("TEST.nope") {
+        Err(CQiError::ClError(CL_ERROR::NO_SUCH_ATTRIBUTE)) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn unexpected_data_is_skipped() {
+    let response = [
+        encode(&[&(DATA::INT_LIST as WORD), &vec![1, 2, 3]]),
+        encode(&[&(DATA::STRING_LIST as WORD), &vec![RawString(vec![0xE4])]]),
+        encode(&[&(STATUS::PING_OK as WORD)]),
+        encode(&[&(DATA::STRING_LIST as WORD), &vec!["TEST".to_string()]]),
+    ].concat();
+
+    let connection = CQiConnection::from_stream(Pipe::new(response));
+    match connection.cl_lexicon_size("TEST.word") {
+        Err(CQiError::UnexpectedResponse { received, .. }) => assert_eq!(received, DATA::INT_LIST as WORD),
+        r => panic!("unexpected result {:?}", r),
+    }
+    // not even valid utf8
+    assert!(connection.cl_attribute_size("TEST.word").is_err());
+
+    connection.ctrl_ping().unwrap();
+    assert_eq!(connection.corpus_list_corpora().unwrap(), vec!["TEST"]);
+}
+
+#[test]
+fn oversized_arguments_are_not_sent() {
+    let mut bytes = Vec::new();
+    let long = "x".repeat(WORD::MAX as usize + 1);
+    match long.as_str().write_cqi_bytes(&mut bytes) {
+        Err(CQiError::InvalidArgument(_)) => assert!(bytes.is_empty()),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert!("x".repeat(WORD::MAX as usize).write_cqi_bytes(&mut bytes).is_ok());
+
+    let ragged: INT_TABLE = vec![vec![1, 2], vec![3]];
+    assert!(ragged.write_cqi_bytes(&mut Vec::new()).is_err());
+
+    let response = (STATUS::PING_OK as WORD).to_be_bytes().to_vec();
+    let connection = CQiConnection::from_stream(Pipe::new(response));
+    connection.set_charset("TEST", Charset::Utf8);
+
+    let query = format!("\"{}\";", long);
+    match connection.cqp_query("TEST", "Long", &query) {
+        Err(CQiError::InvalidArgument(_)) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert!(connection.get_ref().output.is_empty());
+
+    // the connection is still in sync
+    connection.ctrl_ping().unwrap();
+    assert_eq!(connection.get_ref().output, (COMMANDS::CTRL_PING as WORD).to_be_bytes());
+}
+
+#[test]
+fn commands_are_sent_in_one_write() {
+    let mut response = Vec::new();
+    response.extend_from_slice(&(DATA::STRING_LIST as WORD).to_be_bytes());
+    response.extend_from_slice(&1000i32.to_be_bytes());
+    for _ in 0..1000 {
+        response.extend_from_slice(&[0, 4]);
+        response.extend_from_slice(b"word");
+    }
+
+    let connection = CQiConnection::from_stream(Pipe::new(response));
+    connection.set_charset("TEST", Charset::Utf8);
+    let cpos: Vec<INT> = (0..1000).collect();
+    let strings = connection.cl_cpos2str("TEST.word", &cpos).unwrap();
+
+    assert_eq!(strings.len(), 1000);
+    assert!(strings.iter().all(|s| s == "word"));
+    assert_eq!(connection.get_ref().writes, 1);
+    assert_eq!(connection.get_ref().output.len(), 2 + 2 + 9 + 4 + 4 * 1000);
+}
+
+#[test]
+fn fixed_size_values_have_no_length_prefix() {
+    let mut bytes = Vec::new();
+    [3, 7].write_cqi_bytes(&mut bytes).unwrap();
+    assert_eq!(bytes, [0, 0, 0, 3, 0, 0, 0, 7]);
+
+    bytes.clear();
+    [1, 2, 3, 4].write_cqi_bytes(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), 16);
+
+    // what is written is what CL_STRUC2CPOS reads
+    let mut response = (DATA::INT_INT as WORD).to_be_bytes().to_vec();
+    [3, 7].write_cqi_bytes(&mut response).unwrap();
+    let connection = CQiConnection::from_stream(Pipe::new(response));
+    assert_eq!(connection.cl_struc2cpos("TEST.s", 0).unwrap(), [3, 7]);
+}
+
+#[test]
+fn builder_logs_in_and_applies_command_timeouts() {
+    use byteorder::{NetworkEndian, ReadBytesExt};
+    use std::io::{Read, Write};
+    use std::time::Duration;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+
+        assert_eq!(stream.read_u16::<NetworkEndian>().unwrap(), COMMANDS::CTRL_CONNECT as WORD);
+        let mut credentials = [0; 2 + 4 + 2 + 6];
+        stream.read_exact(&mut credentials).unwrap();
+        assert_eq!(&credentials, b"\x00\x04user\x00\x06secret");
+        stream.write_all(&(STATUS::CONNECT_OK as WORD).to_be_bytes()).unwrap();
+
+        // slower than the default read timeout
+        assert_eq!(stream.read_u16::<NetworkEndian>().unwrap(), COMMANDS::CTRL_PING as WORD);
+        std::thread::sleep(Duration::from_millis(300));
+        stream.write_all(&(STATUS::PING_OK as WORD).to_be_bytes()).unwrap();
+    });
+
+    let connection = CQiConnection::builder()
+        .connect_timeout(Some(Duration::from_secs(1)))
+        .read_timeout(Some(Duration::from_millis(100)))
+        .command_timeout(COMMANDS::CTRL_PING, Some(Duration::from_secs(5)))
+        .nodelay(true)
+        .keepalive(Some(Duration::from_secs(60)))
+        .credentials("user", "secret")
+        .connect(addr)
+        .unwrap();
+
+    connection.ctrl_ping().unwrap();
+    server.join().unwrap();
+}
+
+#[cfg(feature = "tokio")]
+mod async_client {
+    use crate::*;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use super::encode;
+
+    // answers each expected request with the scripted response
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, script: Vec<(Vec<u8>, Vec<u8>)>) {
+        for (request, response) in script {
+            let mut received = vec![0; request.len()];
+            stream.read_exact(&mut received).await.unwrap();
+            assert_eq!(received, request);
+            stream.write_all(&response).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn commands_over_tcp() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let script = vec![
+            (
+                encode(&[&(COMMANDS::CTRL_CONNECT as WORD), &"user", &"secret"]),
+                encode(&[&(STATUS::CONNECT_OK as WORD)]),
+            ),
+            (
+                encode(&[&(COMMANDS::CORPUS_LIST_CORPORA as WORD)]),
+                encode(&[&(DATA::STRING_LIST as WORD), &vec!["BNC".to_string(), "DICKENS".to_string()]]),
+            ),
+            (
+                encode(&[&(COMMANDS::CL_STR2ID as WORD), &"BNC.word", &(&["the", "xyzzy"][..])]),
+                encode(&[&(DATA::INT_LIST as WORD), &vec![17, -1]]),
+            ),
+            (
+                encode(&[&(COMMANDS::CTRL_PING as WORD)]),
+                encode(&[&(STATUS::PING_OK as WORD)]),
+            ),
+        ];
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve(stream, script).await;
+        });
+
+        let mut connection = AsyncCQiConnection::connect(addr).await.unwrap();
+        connection.ctr_connect("user", "secret").await.unwrap();
+        assert_eq!(connection.corpus_list_corpora().await.unwrap(), vec!["BNC", "DICKENS"]);
+        connection.set_charset("BNC", Charset::Utf8);
+        assert_eq!(connection.cl_str2id("BNC.word", &["the", "xyzzy"]).await.unwrap(), vec![17, -1]);
+        connection.ctrl_ping().await.unwrap();
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unexpected_data_is_skipped() {
+        let (client, server) = tokio::io::duplex(1024);
+
+        let script = vec![
+            (
+                encode(&[&(COMMANDS::CL_LEXICON_SIZE as WORD), &"BNC.word"]),
+                encode(&[&(DATA::INT_LIST as WORD), &vec![1, 2, 3]]),
+            ),
+            (
+                encode(&[&(COMMANDS::CTRL_PING as WORD)]),
+                encode(&[&(STATUS::PING_OK as WORD)]),
+            ),
+        ];
+
+        let server = tokio::spawn(serve(server, script));
+
+        let mut connection = AsyncCQiConnection::from_stream(client);
+        match connection.cl_lexicon_size("BNC.word").await {
+            Err(CQiError::UnexpectedResponse { received, .. }) => assert_eq!(received, DATA::INT_LIST as WORD),
+            r => panic!("unexpected result {:?}", r),
+        }
+        connection.ctrl_ping().await.unwrap();
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn errors_with_server_messages() {
+        let (client, server) = tokio::io::duplex(1024);
+
+        let script = vec![
+            (
+                encode(&[&(COMMANDS::CORPUS_CHARSET as WORD), &"BNC"]),
+                encode(&[&(DATA::STRING as WORD), &"latin1"]),
+            ),
+            (
+                encode(&[&(COMMANDS::CQP_QUERY as WORD), &"BNC", &"Result", &"[word=\"the\";"]),
+                encode(&[&(CQP_ERROR::GENERAL as WORD)]),
+            ),
+            (
+                encode(&[&(COMMANDS::CTRL_LAST_GENERAL_ERROR as WORD)]),
+                encode(&[&(DATA::STRING as WORD), &"syntax error"]),
+            ),
+            (
+                encode(&[&(COMMANDS::CQP_FDIST_1 as WORD), &"BNC:Result", &0, &(cqi_consts::FIELD::MATCH as BYTE), &"BNC.word"]),
+                encode(&[&(DATA::INT_TABLE as WORD), &vec![vec![3, 10], vec![5, 2]]]),
+            ),
+        ];
+
+        let server = tokio::spawn(serve(server, script));
+
+        let mut connection = AsyncCQiConnection::from_stream(client);
+        connection.set_fetch_error_messages(true);
+
+        match connection.cqp_query("BNC", "Result", "[word=\"the\";").await {
+            Err(CQiError::CqpError(cqi_consts::CQP_ERROR::GENERAL, Some(msg))) => assert_eq!(msg, "syntax error"),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let fdist = connection.cqp_fdist_1("BNC:Result", 0, cqi_consts::FIELD::MATCH, "BNC.word").await.unwrap();
+        assert_eq!(fdist, vec![FdistItem { id: 3, frequency: 10 }, FdistItem { id: 5, frequency: 2 }]);
+
+        server.await.unwrap();
+    }
+}
+
+mod mock_server {
+    use crate::*;
+    use crate::testing::*;
+
+    fn connect() -> CQiConnection {
+        let server = MockServer::new().corpus(MockCorpus::fixture()).spawn().unwrap();
+        let connection = server.connect().unwrap();
+        connection.ctr_connect("user", "password").unwrap();
+        connection
+    }
+
+    #[test]
+    fn corpus_metadata() {
+        let connection = connect();
+
+        assert_eq!(connection.corpus_list_corpora().unwrap(), vec!["TEST"]);
+        assert_eq!(connection.corpus_charset("TEST").unwrap(), "utf8");
+        assert_eq!(connection.corpus_full_name("TEST").unwrap(), "Test corpus");
+        assert_eq!(connection.corpus_positional_attributes("TEST").unwrap(), vec!["word", "pos", "lemma"]);
+        assert!(connection.corpus_structural_attribute_has_values("TEST.text_genre").unwrap());
+        assert!(!connection.corpus_structural_attribute_has_values("TEST.s").unwrap());
+        assert!(connection.corpus_alignment_attributes("TEST").unwrap().is_empty());
+
+        match connection.corpus_info("NOPE") {
+            Err(CQiError::CqpError(CQP_ERROR::NO_SUCH_CORPUS, None)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn low_level_corpus_access() {
+        let connection = connect();
+
+        assert_eq!(connection.cl_attribute_size("TEST.word").unwrap(), 22);
+        assert_eq!(connection.cl_attribute_size("TEST.s").unwrap(), 4);
+        assert_eq!(connection.cl_lexicon_size("TEST.word").unwrap(), 13);
+
+        let ids = connection.cl_str2id("TEST.word", &["cat", "xyzzy"]).unwrap();
+        assert_eq!(ids, vec![1, -1]);
+        assert_eq!(connection.cl_id2freq("TEST.word", &ids).unwrap(), vec![2, 0]);
+        assert_eq!(connection.cl_id2cpos("TEST.word", 1).unwrap(), vec![1, 12]);
+        assert_eq!(connection.cl_cpos2str("TEST.pos", &[1, 2, 99]).unwrap(), vec!["NN", "VBD", ""]);
+        assert_eq!(connection.cl_regex2id("TEST.lemma", "s.*").unwrap(), connection.cl_str2id("TEST.lemma", &["sit", "see"]).unwrap());
+
+        assert_eq!(connection.cl_cpos2struc("TEST.s", &[3, 12]).unwrap(), vec![0, 2]);
+        assert_eq!(connection.cl_cpos2lbound("TEST.text", &[12]).unwrap(), vec![11]);
+        assert_eq!(connection.cl_struc2cpos("TEST.s", 1).unwrap(), [7, 10]);
+        assert_eq!(connection.cl_struc2str("TEST.text_year", &[0, 1]).unwrap(), vec!["1900", "2000"]);
+
+        match connection.cl_lexicon_size("TEST.s") {
+            Err(CQiError::ClError(CL_ERROR::WRONG_ATTRIBUTE_TYPE)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn queries() {
+        let mut connection = connect();
+        connection.set_fetch_error_messages(true);
+
+        connection.cqp_query("TEST", "Det", "[pos=\"DT\"] @[pos=\"NN\"];").unwrap();
+        assert_eq!(connection.cqp_list_subcorpora("TEST").unwrap(), vec!["Det"]);
+        assert_eq!(connection.cqp_subcorpus_size("TEST:Det").unwrap(), 6);
+        assert!(connection.cqp_subcorpus_has_field("TEST:Det", FIELD::TARGET).unwrap());
+        assert!(!connection.cqp_subcorpus_has_field("TEST:Det", FIELD::KEYWORD).unwrap());
+        assert_eq!(connection.cqp_dump_subcorpus("TEST:Det", FIELD::MATCH, 0, 2).unwrap(), vec![0, 4, 7]);
+        assert_eq!(connection.cqp_dump_subcorpus("TEST:Det", FIELD::TARGET, 0, 2).unwrap(), vec![1, 5, 8]);
+
+        let fdist = connection.cqp_fdist_1("TEST:Det", 2, FIELD::TARGET, "TEST.lemma").unwrap();
+        let lemmas = connection.cl_id2str("TEST.lemma", &fdist.iter().map(|f| f.id).collect::<Vec<_>>()).unwrap();
+        assert_eq!(lemmas, vec!["cat", "mat", "dog"]);
+        assert_eq!(fdist.iter().map(|f| f.frequency).collect::<Vec<_>>(), vec![2, 2, 2]);
+
+        connection.cqp_drop_subcorpus("TEST:Det").unwrap();
+        assert!(connection.cqp_list_subcorpora("TEST").unwrap().is_empty());
+
+        match connection.cqp_query("TEST", "Broken", "[pos=\"DT\"") {
+            Err(e @ CQiError::CqpError(CQP_ERROR::GENERAL, Some(_))) => assert!(e.is_general()),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn corpus_handles_cache_metadata() {
+        let server = MockServer::new().corpus(MockCorpus::fixture()).spawn().unwrap();
+        let connection = server.connect().unwrap();
+        let corpus = connection.corpus("TEST");
+
+        for _ in 0..2 {
+            assert_eq!(corpus.full_name().unwrap(), "Test corpus");
+            assert_eq!(corpus.info().unwrap(), ["Test corpus", "for cqi_rs"]);
+            assert_eq!(corpus.positional_attributes().unwrap(), ["word", "pos", "lemma"]);
+            assert_eq!(corpus.size().unwrap(), 22);
+        }
+        assert_eq!(corpus.charset().unwrap(), Charset::Utf8);
+        assert_eq!(corpus.properties().unwrap(), ["charset = \"utf8\""]);
+
+        let pos = corpus.p_attribute("pos").unwrap();
+        assert_eq!(pos.specifier(), "TEST.pos");
+        assert_eq!(pos.name(), "pos");
+        assert_eq!(pos.corpus_name(), "TEST");
+        assert_eq!(corpus.s_attribute("text_year").unwrap().specifier(), "TEST.text_year");
+
+        // attributes are checked against the cached lists
+        assert!(corpus.s_attribute("pos").is_err());
+        assert!(corpus.a_attribute("TEST_EN").is_err());
+
+        assert_eq!(server.log(), vec![
+            COMMANDS::CORPUS_FULL_NAME,
+            // the full name is decoded with the corpus charset
+            COMMANDS::CORPUS_CHARSET,
+            COMMANDS::CORPUS_INFO,
+            COMMANDS::CORPUS_POSITIONAL_ATTRIBUTES,
+            COMMANDS::CL_ATTRIBUTE_SIZE,
+            COMMANDS::CORPUS_PROPERTIES,
+            COMMANDS::CORPUS_STRUCTURAL_ATTRIBUTES,
+            COMMANDS::CORPUS_ALIGNMENT_ATTRIBUTES,
+        ]);
+
+        match connection.corpus("NOPE").size() {
+            Err(CQiError::ClError(CL_ERROR::NO_SUCH_ATTRIBUTE)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn typed_attribute_handles() {
+        let corpus = MockCorpus::fixture().a_attribute("test_de", &[[0, 6, 0, 7], [7, 10, 8, 11]]);
+        let server = MockServer::new().corpus(corpus).spawn().unwrap();
+        let connection = server.connect().unwrap();
+        let corpus = connection.corpus("TEST");
+
+        let word = corpus.p_attribute("word").unwrap();
+        assert_eq!(word.size().unwrap(), 22);
+        assert_eq!(word.lexicon_size().unwrap(), 13);
+        let ids = word.str2id(&["cat", "dog"]).unwrap();
+        assert_eq!(word.id2freq(&ids).unwrap(), vec![2, 2]);
+        assert_eq!(word.id2str(&ids).unwrap(), vec!["cat", "dog"]);
+        assert_eq!(word.idlist2cpos(&ids).unwrap(), vec![1, 8, 12, 15]);
+        assert_eq!(word.cpos2str(&[0]).unwrap(), vec!["The"]);
+
+        let s = corpus.s_attribute("s").unwrap();
+        assert_eq!(s.size().unwrap(), 4);
+        assert_eq!(s.cpos2struc(&[8]).unwrap(), vec![1]);
+        assert_eq!(s.cpos2rbound(&[8]).unwrap(), vec![10]);
+        assert_eq!(s.struc2cpos(3).unwrap(), [17, 21]);
+        match s.struc2str(&[0]) {
+            Err(CQiError::InvalidArgument(_)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let genre = corpus.s_attribute("text_genre").unwrap();
+        assert_eq!(genre.struc2str(&[1, 0]).unwrap(), vec!["news", "fiction"]);
+        genre.struc2str(&[0]).unwrap();
+
+        let de = corpus.a_attribute("test_de").unwrap();
+        assert_eq!(de.cpos2alg(&[8]).unwrap(), vec![1]);
+        assert_eq!(de.alg2cpos(1).unwrap(), [7, 10, 8, 11]);
+
+        // has_values is asked once per handle, struc2str is never sent for s
+        let log = server.log();
+        assert_eq!(log.iter().filter(|&&c| c == COMMANDS::CORPUS_STRUCTURAL_ATTRIBUTE_HAS_VALUES).count(), 2);
+        assert_eq!(log.iter().filter(|&&c| c == COMMANDS::CL_STRUC2STR).count(), 2);
+    }
+
+    #[test]
+    fn frequencies_are_sorted_rows() {
+        let connection = connect();
+        let corpus = connection.corpus("TEST");
+        let lemma = corpus.p_attribute("lemma").unwrap();
+        let row = |string: &str, frequency: INT| (string.to_string(), frequency);
+
+        // the server lists "mat" before "dog", ties are sorted by string
+        let all = corpus.query("All", "[];").unwrap();
+        let rows = all.frequencies(2, FIELD::MATCH, &lemma).unwrap();
+        assert_eq!(rows, [row("the", 5), row(".", 4), row("cat", 2), row("dog", 2), row("mat", 2)]);
+
+        let det = corpus.query("Det", "[pos=\"DT\"] @[pos=\"NN\"];").unwrap();
+        let rows = det.frequencies_2(2, FIELD::MATCH, &lemma, FIELD::TARGET, &lemma).unwrap();
+        assert_eq!(rows, [(("the".to_string(), "dog".to_string()), 2), (("the".to_string(), "mat".to_string()), 2)]);
+        let word = corpus.p_attribute("word").unwrap();
+        let rows = det.frequencies_2(0, FIELD::MATCH, &word, FIELD::TARGET, &lemma).unwrap();
+        assert_eq!(rows[0], (("A".to_string(), "cat".to_string()), 1));
+        assert_eq!(rows.len(), 6);
+    }
+
+    #[test]
+    fn subcorpora_are_dropped_with_their_handle() {
+        let connection = connect();
+        let corpus = connection.corpus("TEST");
+        let lemma = corpus.p_attribute("lemma").unwrap();
+
+        {
+            let det = corpus.query("Det", "[pos=\"DT\"] @[pos=\"NN\"];").unwrap();
+            assert_eq!(det.specifier(), "TEST:Det");
+            assert_eq!(det.mother(), "TEST");
+            assert_eq!(det.size().unwrap(), 6);
+            assert!(det.has_field(FIELD::TARGET).unwrap());
+            assert_eq!(det.dump(FIELD::MATCHEND, 0, 1).unwrap(), vec![1, 5]);
+
+            let fdist = det.fdist_1(2, FIELD::TARGET, &lemma).unwrap();
+            assert_eq!(fdist.len(), 3);
+            let pairs = det.fdist_2(0, FIELD::MATCH, &lemma, FIELD::TARGET, &lemma).unwrap();
+            assert_eq!(pairs.iter().map(|p| p.frequency).sum::<INT>(), 6);
+
+            assert_eq!(corpus.subcorpora().unwrap(), vec!["Det"]);
+        }
+        assert!(corpus.subcorpora().unwrap().is_empty());
+
+        let kept = corpus.query("Kept", "\"cat\";").unwrap().persist();
+        assert!(kept.is_persistent());
+        drop(kept);
+        assert_eq!(corpus.subcorpus("Kept").size().unwrap(), 2);
+
+        let dropped = corpus.query("Dropped", "\"dog\";").unwrap();
+        dropped.drop_subcorpus().unwrap();
+        corpus.subcorpus("Kept").drop_subcorpus().unwrap();
+        assert!(corpus.subcorpora().unwrap().is_empty());
+
+        let size = corpus.subcorpus("Kept").size();
+        match size {
+            Err(CQiError::CqpError(CQP_ERROR::NO_SUCH_CORPUS, _)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn subcorpus_names() {
+        let connection = connect();
+        let corpus = connection.corpus("TEST");
+
+        for name in &["det", "1Det", "Det:s", "", "Dét"] {
+            match corpus.query(name, "\"cat\";") {
+                Err(CQiError::InvalidArgument(_)) => (),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+        assert!(corpus.subcorpora().unwrap().is_empty());
+
+        // the terminating ; is added
+        let cats = corpus.query("Cat_s-1", " \"cat\" ").unwrap();
+        assert_eq!(cats.size().unwrap(), 2);
+
+        let taken = corpus.query("Cqi1", "\"dog\"").unwrap();
+        let first = corpus.query_unnamed("\"mat\"").unwrap();
+        let second = corpus.query_unnamed("\"mat\"").unwrap();
+        assert_eq!(taken.name(), "Cqi1");
+        assert_eq!(first.name(), "Cqi2");
+        assert_eq!(second.name(), "Cqi3");
+        assert_eq!(second.size().unwrap(), 2);
+    }
+
+    #[test]
+    fn concordance_lines() {
+        let connection = connect();
+        let det = connection.corpus("TEST").query("Det", "[pos=\"DT\"] [pos=\"NN\"];").unwrap();
+        let strings = |tokens: &[STRING_LIST]| tokens.iter().map(|t| t.join("/")).collect::<Vec<_>>();
+
+        let lines = det.concordance().attributes(&["word", "pos"]).context(Context::Tokens(4)).all_lines().unwrap();
+        assert_eq!(lines.len(), 6);
+        assert_eq!((lines[0].match_start, lines[0].match_end), (0, 1));
+        assert_eq!(strings(&lines[0].keyword), ["The/DT", "cat/NN"]);
+        assert_eq!(strings(&lines[0].right), ["sat/VBD", "on/IN", "the/DT", "mat/NN"]);
+        // clamped at both ends of the corpus
+        assert!(lines[0].left.is_empty());
+        assert_eq!(strings(&lines[5].left), ["saw/VBD", "the/DT", "dog/NN", "./SENT"]);
+        assert_eq!(strings(&lines[5].right), ["was/VBD", "red/JJ", "./SENT"]);
+
+        // huge contexts are cut at the corpus boundaries instead of overflowing
+        let lines = det.concordance().context(Context::Tokens(INT::MAX)).lines(0, 0).unwrap();
+        assert!(lines[0].left.is_empty());
+        assert_eq!(lines[0].right.len(), 20);
+        let lines = det.concordance().context(Context::Tokens(INT::MAX)).lines(5, 5).unwrap();
+        assert_eq!(lines[0].left.len(), 17);
+        assert_eq!(strings(&lines[0].right), ["was", "red", "."]);
+
+        let lines = det.concordance()
+            .left(Context::Region("s".to_string()))
+            .right(Context::Region("text".to_string()))
+            .lines(1, 2)
+            .unwrap();
+        assert_eq!(strings(&lines[0].left), ["The", "cat", "sat", "on"]);
+        assert_eq!(strings(&lines[0].right), [".", "The", "dog", "barked", "."]);
+        assert!(lines[1].left.is_empty());
+        assert_eq!(strings(&lines[1].right), ["barked", "."]);
+    }
+
+    #[test]
+    fn short_concordance_replies_are_invalid_data() {
+        let server = MockServer::new()
+            .corpus(MockCorpus::fixture())
+            .on(COMMANDS::CL_CPOS2LBOUND, |_| Response::int_list(vec![0]))
+            .on(COMMANDS::CL_CPOS2STR, |_| Response::string_list(vec!["The".to_string()]))
+            .spawn()
+            .unwrap();
+        let connection = server.connect().unwrap();
+        let det = connection.corpus("TEST").query("Det", "[pos=\"DT\"] [pos=\"NN\"];").unwrap();
+
+        let concordance = det.concordance().left(Context::Region("s".to_string())).right(Context::Tokens(0));
+        match concordance.lines_for(&[0, 4], &[1, 5]) {
+            Err(CQiError::InvalidData(_)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match det.concordance().context(Context::Tokens(0)).lines_for(&[0, 4], &[1, 5]) {
+            Err(CQiError::InvalidData(_)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn paged_matches() {
+        let server = MockServer::new().corpus(MockCorpus::fixture()).spawn().unwrap();
+        let connection = server.connect().unwrap();
+        let det = connection.corpus("TEST").query("Det", "[pos=\"DT\"] @[pos=\"NN\"];").unwrap();
+
+        let matches = det.matches(4).unwrap().collect::<CQiResult<Vec<_>>>().unwrap();
+        assert_eq!(matches.len(), 6);
+        assert_eq!(matches[1], Match { start: 4, end: 5, target: Some(5), keyword: None });
+        // two pages of MATCH, MATCHEND and TARGET
+        let dumps = server.log().iter().filter(|&&c| c == COMMANDS::CQP_DUMP_SUBCORPUS).count();
+        assert_eq!(dumps, 6);
+
+        let lines = det.concordance().context(Context::Tokens(1)).paged(5).unwrap();
+        let keywords = lines.map(|l| l.unwrap().keyword.concat().join(" ")).collect::<Vec<_>>();
+        assert_eq!(keywords, ["The cat", "the mat", "The dog", "A cat", "the dog", "The mat"]);
+
+        assert!(det.matches(0).is_err());
+    }
+
+    #[test]
+    fn short_dumps_are_invalid_data() {
+        // every field but MATCH is missing the second match
+        let server = MockServer::new()
+            .corpus(MockCorpus::fixture())
+            .on(COMMANDS::CQP_DUMP_SUBCORPUS, |r| match r.byte(1) {
+                b if b == FIELD::MATCH as BYTE => Response::int_list(vec![0, 4]),
+                _ => Response::int_list(vec![1]),
+            })
+            .spawn()
+            .unwrap();
+        let connection = server.connect().unwrap();
+        let det = connection.corpus("TEST").query("Det", "[pos=\"DT\"] [pos=\"NN\"];").unwrap();
+
+        match det.matches(2).unwrap().next() {
+            Some(Err(CQiError::InvalidData(_))) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn collocations() {
+        let connection = connect();
+        let corpus = connection.corpus("TEST");
+        let lemma = corpus.p_attribute("lemma").unwrap();
+        let cat = corpus.query("Cat", "\"cat\";").unwrap();
+
+        let collocates = cat.collocations(&lemma).window(1, 1).compute().unwrap();
+        let strings = collocates.iter().map(|c| c.string.as_str()).collect::<Vec<_>>();
+        assert_eq!(strings, ["a", "see", "sit", "the"]);
+
+        let sit = &collocates[2];
+        assert_eq!((sit.frequency, sit.corpus_frequency), (1, 1));
+        assert!((sit.expected - 4.0 / 22.0).abs() < 1e-9);
+        assert!((sit.mutual_information - 5.5f64.log2()).abs() < 1e-9);
+        assert!((sit.log_likelihood - 3.6370).abs() < 1e-3);
+        assert!((sit.log_ratio - 9f64.log2()).abs() < 1e-9);
+        assert!((sit.dice - 0.4).abs() < 1e-9);
+
+        let the = &collocates[3];
+        assert_eq!((the.frequency, the.corpus_frequency), (1, 5));
+        assert!(the.mutual_information < sit.mutual_information);
+
+        let collocates = cat.collocations(&lemma).window(2, 0).min_frequency(2).compute().unwrap();
+        assert!(collocates.is_empty());
+
+        // a window past the end of the corpus takes everything but the matches
+        let collocates = cat.collocations(&lemma).window(1, INT::MAX).compute().unwrap();
+        assert_eq!(collocates.iter().map(|c| c.frequency).sum::<INT>(), 20);
+        assert_eq!(collocates.iter().find(|c| c.string == "the").unwrap().frequency, 5);
+
+        // windows overlapping across pages are counted once either way
+        let det = corpus.query("Det", "[pos=\"DT\"] [pos=\"NN\"];").unwrap();
+        let paged = det.collocations(&lemma).window(3, 3).page_size(1).compute().unwrap();
+        assert_eq!(paged, det.collocations(&lemma).window(3, 3).compute().unwrap());
+        assert_eq!(paged.iter().map(|c| c.frequency).sum::<INT>(), 10);
+    }
+
+    #[test]
+    fn keyness() {
+        let connection = connect();
+        let corpus = connection.corpus("TEST");
+        let det = corpus.query("Det", "[pos=\"DT\"] [pos=\"NN\"];").unwrap();
+
+        // the matches are counted in the reference as well by default
+        let keywords = Keyness::new(&det, &corpus, "word").compute().unwrap();
+        assert_eq!(keywords.len(), 13);
+        let cat = keywords.iter().find(|k| k.string == "cat").unwrap();
+        assert_eq!((cat.target_frequency, cat.reference_frequency), (2, 2));
+        assert!((cat.log_ratio - (22.0f64 / 12.0).log2()).abs() < 1e-9);
+        assert!((cat.percent_diff - 250.0 / 3.0).abs() < 1e-9);
+        assert!((cat.chi_square - 34.0 * 400.0 / 31680.0).abs() < 1e-9);
+        assert!((cat.odds_ratio - 2.0).abs() < 1e-9);
+        let stop = keywords.iter().find(|k| k.string == ".").unwrap();
+        assert_eq!((stop.target_frequency, stop.reference_frequency), (0, 4));
+        assert!(!stop.is_positive());
+
+        let keywords = Keyness::new(&det, &corpus, "word")
+            .positive_only(true)
+            .sort_by(KeynessMeasure::PercentDiff)
+            .compute()
+            .unwrap();
+        let mut strings = keywords.iter().map(|k| k.string.as_str()).collect::<Vec<_>>();
+        assert!(keywords.windows(2).all(|w| w[0].percent_diff >= w[1].percent_diff));
+        strings.sort_unstable();
+        assert_eq!(strings, ["A", "The", "cat", "dog", "mat", "the"]);
+        assert!(Keyness::new(&det, &corpus, "word").min_log_likelihood(3.84).compute().unwrap().is_empty());
+
+        // the matches against the other 10 tokens of the corpus
+        let keywords = Keyness::new(&det, &corpus, "word").exclude_target(true).compute().unwrap();
+        let cat = keywords.iter().find(|k| k.string == "cat").unwrap();
+        assert_eq!((cat.target_frequency, cat.reference_frequency), (2, 0));
+        assert!((cat.log_ratio - (10.0f64 / 3.0).log2()).abs() < 1e-9);
+        assert!((cat.odds_ratio - 4.0).abs() < 1e-9);
+        let stop = keywords.iter().find(|k| k.string == ".").unwrap();
+        assert_eq!((stop.target_frequency, stop.reference_frequency), (0, 4));
+        let included = Keyness::new(&det, &corpus, "word").compute().unwrap();
+        assert!(cat.log_likelihood > included.iter().find(|k| k.string == "cat").unwrap().log_likelihood);
+        // no effect on a reference that doesn't contain the target
+        let excluded = Keyness::new(&det, &det, "word").exclude_target(true).compute().unwrap();
+        assert_eq!(excluded, Keyness::new(&det, &det, "word").compute().unwrap());
+    }
+
+    #[test]
+    fn hits_per_value() {
+        let connection = connect();
+        let corpus = connection.corpus("TEST");
+        let det = corpus.query("Det", "[pos=\"DT\"] [pos=\"NN\"];").unwrap();
+        let cat = corpus.query("Cat", "\"cat\";").unwrap();
+        let genre = corpus.s_attribute("text_genre").unwrap();
+
+        let distribution = det.distribution(&genre).unwrap();
+        assert_eq!(distribution, [
+            ValueDistribution { value: "fiction".to_string(), hits: 3, regions: 1, tokens: 11, per_million: 3e6 / 11.0 },
+            ValueDistribution { value: "news".to_string(), hits: 3, regions: 1, tokens: 11, per_million: 3e6 / 11.0 },
+        ]);
+        let hits = cat.distribution(&genre).unwrap().iter().map(|v| v.hits).collect::<Vec<_>>();
+        assert_eq!(hits, [1, 1]);
+
+        match det.distribution(&corpus.s_attribute("s").unwrap()) {
+            Err(CQiError::InvalidArgument(_)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn dispersion() {
+        let server = MockServer::new().corpus(MockCorpus::fixture()).spawn().unwrap();
+        let connection = server.connect().unwrap();
+        let corpus = connection.corpus("TEST");
+        let lemma = corpus.p_attribute("lemma").unwrap();
+        let (text, s) = (corpus.s_attribute("text").unwrap(), corpus.s_attribute("s").unwrap());
+        let ids = lemma.str2id(&["cat", "dog", "the", "red"]).unwrap();
+
+        let texts = lemma.dispersion(&ids, &text).unwrap();
+        assert_eq!(texts[0], Dispersion { frequency: 2, range: 2, juilland_d: 1.0, gries_dp: 0.0, arf: 2.0 });
+        assert!((texts[1].arf - 18.0 / 11.0).abs() < 1e-9);
+        assert_eq!((texts[3].range, texts[3].gries_dp), (1, 0.5));
+
+        let the = &lemma.dispersion(&ids[2..3], &s).unwrap()[0];
+        assert_eq!((the.frequency, the.range), (5, 4));
+        assert!((the.gries_dp - 0.1).abs() < 1e-9);
+        assert!((the.juilland_d - 0.8832).abs() < 1e-3);
+
+        let animals = lemma.group_dispersion(&ids[..2], &text).unwrap();
+        assert_eq!((animals.frequency, animals.range, animals.gries_dp), (4, 2, 0.0));
+
+        // the regions are fetched once per handle
+        let regions = server.log().iter().filter(|&&c| c == COMMANDS::CL_STRUC2CPOS).count();
+        assert_eq!(regions, 2 + 4);
+    }
+
+    #[test]
+    fn legacy_charsets() {
+        let corpus = MockCorpus::new("OLD")
+            .charset("latin1")
+            .full_name("Grüße aus Köln")
+            .info(&["Köln"])
+            .p_attribute("word", &["Grüße", "aus", "Köln"])
+            .s_attribute_with_values("text_city", &[([0, 2], "Köln")]);
+        let server = MockServer::new().corpus(corpus).spawn().unwrap();
+        let connection = server.connect().unwrap();
+
+        assert_eq!(connection.charset("OLD.word").unwrap(), Charset::Latin1);
+        assert_eq!(connection.corpus_full_name("OLD").unwrap(), "Grüße aus Köln");
+        assert_eq!(connection.corpus_info("OLD").unwrap(), vec!["Köln"]);
+        assert_eq!(connection.corpus_full_name_raw("OLD").unwrap(), RawString(b"Gr\xfc\xdfe aus K\xf6ln".to_vec()));
+        assert_eq!(connection.cl_cpos2str("OLD.word", &[0, 2]).unwrap(), vec!["Grüße", "Köln"]);
+        assert_eq!(connection.cl_cpos2str_raw("OLD.word", &[2]).unwrap(), vec![RawString(b"K\xf6ln".to_vec())]);
+        assert_eq!(connection.cl_struc2str("OLD.text_city", &[0]).unwrap(), vec!["Köln"]);
+        assert_eq!(connection.cl_str2id("OLD.word", &["Köln"]).unwrap(), vec![2]);
+        assert_eq!(connection.cl_str2id_raw("OLD.word", &[RawString(b"Gr\xfc\xdfe".to_vec())]).unwrap(), vec![0]);
+
+        connection.cqp_query("OLD", "City", "\"Köln\";").unwrap();
+        assert_eq!(connection.cqp_subcorpus_size("OLD:City").unwrap(), 1);
+
+        // the euro sign was never representable in latin1 and is refused before sending
+        match connection.cl_str2id("OLD.word", &["€"]) {
+            Err(CQiError::InvalidArgument(_)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // the charset is only asked for once
+        let log = server.log();
+        assert_eq!(log.iter().filter(|&&c| c == COMMANDS::CORPUS_CHARSET).count(), 1);
+
+        // overrides apply to the whole corpus, whatever it's named by
+        connection.set_charset("OLD.word", Charset::Utf8);
+        assert_eq!(connection.charset("OLD:City").unwrap(), Charset::Utf8);
+        connection.set_charset("OLD:City", Charset::Latin1);
+        assert_eq!(connection.charset("OLD").unwrap(), Charset::Latin1);
+
+        let latin9 = Charset::from_name("latin9").unwrap();
+        assert_eq!(latin9.encode("€").unwrap().as_ref(), b"\xa4");
+        assert_eq!(latin9.decode(vec![0xa4]).unwrap(), "€");
+        assert!(Charset::Utf8.decode(vec![0xa4]).is_err());
+    }
+
+    #[test]
+    fn error_messages_are_fetched_once() {
+        let server = MockServer::new()
+            .script(COMMANDS::CTRL_PING, vec![])
+            .script(COMMANDS::CTRL_LAST_GENERAL_ERROR, vec![])
+            .spawn()
+            .unwrap();
+        let mut connection = server.connect().unwrap();
+        connection.set_fetch_error_messages(true);
+
+        // the error of CTRL_LAST_GENERAL_ERROR isn't looked up in turn
+        match connection.ctrl_ping() {
+            Err(CQiError::Error(ERROR::GENERAL_ERROR, None)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(connection.ctrl_ping().is_err());
+        assert_eq!(server.log(), vec![
+            COMMANDS::CTRL_PING,
+            COMMANDS::CTRL_LAST_GENERAL_ERROR,
+            COMMANDS::CTRL_PING,
+            COMMANDS::CTRL_LAST_GENERAL_ERROR,
+        ]);
+    }
+
     #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+    fn handlers_and_credentials() {
+        let server = MockServer::new()
+            .credentials("user", "secret")
+            .on(COMMANDS::CORPUS_LIST_CORPORA, |_| Response::string_list(vec!["A".to_string(), "B".to_string()]))
+            .script(COMMANDS::CTRL_PING, vec![Response::Status(STATUS::PING_OK)])
+            .spawn()
+            .unwrap();
+
+        let connection = server.connect().unwrap();
+        match connection.ctr_connect("user", "wrong") {
+            Err(CQiError::Error(ERROR::CONNECT_REFUSED, None)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        connection.ctr_connect("user", "secret").unwrap();
+
+        assert_eq!(connection.corpus_list_corpora().unwrap(), vec!["A", "B"]);
+        connection.ctrl_ping().unwrap();
+        assert!(connection.ctrl_ping().is_err());
+
+        assert_eq!(server.log(), vec![
+            COMMANDS::CTRL_CONNECT,
+            COMMANDS::CTRL_CONNECT,
+            COMMANDS::CORPUS_LIST_CORPORA,
+            COMMANDS::CTRL_PING,
+            COMMANDS::CTRL_PING,
+        ]);
     }
 }